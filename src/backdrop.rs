@@ -0,0 +1,193 @@
+//! What is drawn behind the image: the `--color`, a `--gradient` or a
+//! `--pattern`.
+//!
+//! Images are composited over the backdrop before they are uploaded, so
+//! transparent parts of an image (and the padding of the fit, center and
+//! tile modes) show the configured color instead of black.
+
+use std::str::FromStr;
+
+use image::{ImageBuffer, Rgba};
+
+use color;
+use pattern::Pattern;
+use precise::PreciseImage;
+
+#[derive(Debug, Clone)]
+pub enum Backdrop {
+    /// A single solid color.
+    Color(Rgba<u8>),
+
+    /// A linear gradient through evenly spaced colors.
+    Gradient(Gradient),
+
+    /// A procedural pattern, see `pattern`.
+    Pattern(Pattern),
+}
+
+impl Backdrop {
+    /// Renders the backdrop at the given resolution.
+    pub fn render(&self, width: u32, height: u32) -> PreciseImage {
+        match *self {
+            Backdrop::Color(color) => ImageBuffer::from_pixel(width, height, Rgba {
+                data: [color[0] as f32, color[1] as f32, color[2] as f32, color[3] as f32]
+            }),
+            Backdrop::Gradient(ref gradient) => gradient.render(width, height),
+            Backdrop::Pattern(ref pattern) => pattern.render(width, height),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Gradient {
+    stops: Vec<Rgba<u8>>,
+    /// Direction of the gradient in degrees, clockwise.
+    /// 0 runs top to bottom, 90 runs left to right.
+    angle: f64,
+}
+
+impl Gradient {
+    /// A gradient through `stops` from top to bottom.
+    pub fn new(stops: Vec<Rgba<u8>>) -> Gradient {
+        Gradient { stops: stops, angle: 0.0 }
+    }
+
+    pub fn set_angle(&mut self, angle: f64) {
+        self.angle = angle;
+    }
+
+    /// The color at position `t`, where 0 is the first and 1 the last stop.
+    /// Channels are between 0 and 255, but not rounded yet.
+    pub fn color_at(&self, t: f64) -> [f64; 4] {
+        let last = self.stops.len() - 1;
+        if last == 0 {
+            let stop = self.stops[0];
+            return [stop[0] as f64, stop[1] as f64, stop[2] as f64, stop[3] as f64]
+        }
+        let position = t.max(0.0).min(1.0) * last as f64;
+        let index = (position.floor() as usize).min(last - 1);
+        let fraction = position - index as f64;
+        let (from, to) = (self.stops[index], self.stops[index + 1]);
+        let mut color = [0.0; 4];
+        for c in 0..4 {
+            color[c] = from[c] as f64 + (to[c] as f64 - from[c] as f64) * fraction;
+        }
+        color
+    }
+
+    pub fn render(&self, width: u32, height: u32) -> PreciseImage {
+        let (sin, cos) = self.angle.to_radians().sin_cos();
+        let (dx, dy) = (sin, cos);
+        // Distance from the center to the furthest corner along the direction,
+        // so the first and last stops land exactly on the corners.
+        let half_extent = (dx.abs() * width as f64 + dy.abs() * height as f64) / 2.0;
+        let (cx, cy) = (width as f64 / 2.0, height as f64 / 2.0);
+        ImageBuffer::from_fn(width, height, |x, y| {
+            let projection = (x as f64 + 0.5 - cx) * dx + (y as f64 + 0.5 - cy) * dy;
+            let t = if half_extent > 0.0 {
+                0.5 + projection / (2.0 * half_extent)
+            } else {
+                0.0
+            };
+            let color = self.color_at(t);
+            Rgba { data: [color[0] as f32, color[1] as f32, color[2] as f32, color[3] as f32] }
+        })
+    }
+}
+
+impl FromStr for Gradient {
+    type Err = String;
+
+    /// Parses a comma separated list of colors, e.g. `ff0000,0000ff`.
+    fn from_str(s: &str) -> Result<Gradient, String> {
+        let stops = color::split_list(s).into_iter()
+            .map(color::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Gradient::new(stops))
+    }
+}
+
+/// Composites `src` over `dst` in place. Both images are straight (not
+/// premultiplied) RGBA and must have the same dimensions.
+pub fn composite(dst: &mut PreciseImage, src: &PreciseImage) {
+    assert_eq!(dst.dimensions(), src.dimensions());
+    composite_at(dst, src, 0, 0);
+}
+
+/// Composites `src` over `dst` with its top left corner at `x`, `y`,
+/// clipping what falls outside of `dst`.
+///
+/// Unlike `imageops::overlay` this keeps fully transparent pixels over a
+/// transparent `dst` transparent instead of dividing by a zero alpha.
+pub fn composite_at(dst: &mut PreciseImage, src: &PreciseImage, x: u32, y: u32) {
+    let (dst_width, dst_height) = dst.dimensions();
+    let (src_width, src_height) = src.dimensions();
+    for src_y in 0..src_height.min(dst_height.saturating_sub(y)) {
+        for src_x in 0..src_width.min(dst_width.saturating_sub(x)) {
+            let src = src.get_pixel(src_x, src_y);
+            let dst = dst.get_pixel_mut(x + src_x, y + src_y);
+            *dst = over(src, dst);
+        }
+    }
+}
+
+/// `src` composited over `dst`.
+pub fn over(src: &Rgba<f32>, dst: &Rgba<f32>) -> Rgba<f32> {
+    if src[3] <= 0.0 {
+        return *dst
+    } else if src[3] >= 255.0 {
+        return *src
+    }
+    let src_alpha = src[3] / 255.0;
+    let dst_alpha = dst[3] / 255.0 * (1.0 - src_alpha);
+    let out_alpha = src_alpha + dst_alpha;
+    let mut data = [0.0; 4];
+    for c in 0..3 {
+        data[c] = (src[c] * src_alpha + dst[c] * dst_alpha) / out_alpha;
+    }
+    data[3] = out_alpha * 255.0;
+    Rgba { data: data }
+}
+
+#[test]
+fn test_gradient() {
+    let gradient = "#000000,#ff0000,#ffffff".parse::<Gradient>().unwrap();
+    assert_eq!(gradient.color_at(0.0), [0.0, 0.0, 0.0, 255.0]);
+    assert_eq!(gradient.color_at(0.5), [255.0, 0.0, 0.0, 255.0]);
+    assert_eq!(gradient.color_at(1.0), [255.0, 255.0, 255.0, 255.0]);
+    assert_eq!(gradient.color_at(2.0), gradient.color_at(1.0));
+
+    // Pixel centers are half a pixel inside the corners, so the rows of a
+    // top to bottom gradient are symmetric around the middle stop.
+    let mut gradient = "#000000,#ffffff".parse::<Gradient>().unwrap();
+    let image = gradient.render(4, 4);
+    for x in 0..4 {
+        assert_eq!(image.get_pixel(x, 0)[0], 31.875);
+        assert_eq!(image.get_pixel(x, 3)[0], 223.125);
+    }
+    // 90 degrees runs left to right.
+    gradient.set_angle(90.0);
+    let image = gradient.render(4, 2);
+    for y in 0..2 {
+        assert!((image.get_pixel(0, y)[0] - 31.875).abs() < 1e-3);
+        assert!((image.get_pixel(3, y)[0] - 223.125).abs() < 1e-3);
+        assert!(image.get_pixel(1, y)[0] < image.get_pixel(2, y)[0]);
+    }
+}
+
+#[test]
+fn test_composite_transparent() {
+    let mut dst: PreciseImage = ImageBuffer::from_pixel(2, 1, Rgba { data: [0.0; 4] });
+    let mut src: PreciseImage = ImageBuffer::from_pixel(2, 1, Rgba { data: [0.0; 4] });
+    src.put_pixel(1, 0, Rgba { data: [200.0, 100.0, 50.0, 128.0] });
+    composite(&mut dst, &src);
+    // Nothing over nothing stays transparent, and a translucent pixel over
+    // nothing keeps its color instead of being darkened.
+    assert_eq!(dst.get_pixel(0, 0).data, [0.0; 4]);
+    assert_eq!(dst.get_pixel(1, 0).data, [200.0, 100.0, 50.0, 128.0]);
+
+    let red = Rgba { data: [255.0, 0.0, 0.0, 255.0] };
+    let half_blue = Rgba { data: [0.0, 0.0, 255.0, 127.5] };
+    let blended = over(&half_blue, &red);
+    assert_eq!(blended.data, [127.5, 0.0, 127.5, 255.0]);
+}
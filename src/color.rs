@@ -0,0 +1,342 @@
+//! Parsing of the colors given on the command line, for the background
+//! color, gradient stops and tints.
+//!
+//! Colors can be written as:
+//!
+//! - a decimal number, or six hex digits without a prefix (`1a1a2e`)
+//! - `#rgb`, `#rrggbb` or `#rrggbbaa`
+//! - `rgb(26, 26, 46)`, `rgba(26, 26, 46, 0.5)`, with numbers from 0 to 255
+//!   or percentages, and the same with spaces and `/ ALPHA`
+//! - `hsl(240, 28%, 14%)` and `hsla(...)`
+//! - a CSS or X11 color name (`steelblue`, `Light Goldenrod`, `gray40`)
+//! - an entry of the theme's palette (`wal:color4`, `xrdb:background`), see
+//!   `palette`
+
+use image::Rgba;
+
+use palette;
+
+/// Parses a color, see the module documentation for the syntax.
+pub fn parse(color: &str) -> Result<Rgba<u8>, String> {
+    let color = color.trim();
+    match palette::resolve(color) {
+        Some(resolved) => parse_value(&resolved?)
+            .map_err(|err| format!("{} (from {})", err, color)),
+        None => parse_value(color),
+    }
+}
+
+/// Parses a color that is no palette reference.
+fn parse_value(color: &str) -> Result<Rgba<u8>, String> {
+    let color = color.trim();
+    let invalid = || format!("Invalid color: {}", color);
+    if color.is_empty() {
+        return Err(invalid())
+    }
+    if color.starts_with('#') {
+        return parse_hex(&color[1..]).ok_or_else(invalid)
+    }
+    if color.ends_with(')') {
+        return parse_function(color).ok_or_else(invalid)
+    }
+    if color.chars().all(|c| c.is_ascii_digit()) {
+        return color.parse::<u32>().map(from_u32).map_err(|_| invalid())
+    }
+    if color.len() == 6 {
+        if let Ok(rgb) = u32::from_str_radix(color, 16) {
+            return Ok(from_u32(rgb))
+        }
+    }
+    named(color).ok_or_else(|| format!("Invalid color, expected hex digits, #rgb, rgb(), \
+                                        hsl() or a color name: {}", color))
+}
+
+/// Splits a comma separated list of colors, leaving the commas inside
+/// `rgb(...)` and `hsl(...)` alone.
+pub fn split_list(list: &str) -> Vec<&str> {
+    let mut colors = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+    for (i, c) in list.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                colors.push(list[start..i].trim());
+                start = i + 1;
+            },
+            _ => {},
+        }
+    }
+    colors.push(list[start..].trim());
+    colors
+}
+
+/// An opaque color from the lowest 24 bits, as `0xRRGGBB`.
+fn from_u32(rgb: u32) -> Rgba<u8> {
+    Rgba { data: [(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8, 255] }
+}
+
+/// Parses the digits of `#rgb`, `#rrggbb` or `#rrggbbaa`.
+fn parse_hex(digits: &str) -> Option<Rgba<u8>> {
+    let digit = |i: usize| digits.get(i..i + 1)
+        .and_then(|digit| u8::from_str_radix(digit, 16).ok());
+    let byte = |i: usize| Some(digit(i)? << 4 | digit(i + 1)?);
+    match digits.len() {
+        3 => Some(Rgba { data: [digit(0)? * 17, digit(1)? * 17, digit(2)? * 17, 255] }),
+        6 => Some(Rgba { data: [byte(0)?, byte(2)?, byte(4)?, 255] }),
+        8 => Some(Rgba { data: [byte(0)?, byte(2)?, byte(4)?, byte(6)?] }),
+        _ => None,
+    }
+}
+
+/// Parses a number, or a percentage of `full`.
+fn parse_number(s: &str, full: f64) -> Option<f64> {
+    if s.ends_with('%') {
+        s[..s.len() - 1].parse::<f64>().ok().map(|percent| percent / 100.0 * full)
+    } else {
+        s.parse().ok()
+    }
+}
+
+/// Parses `rgb()`, `rgba()`, `hsl()` and `hsla()`, with the arguments
+/// separated by commas or by spaces and a `/` before the alpha.
+fn parse_function(color: &str) -> Option<Rgba<u8>> {
+    let open = color.find('(')?;
+    let name = color[..open].trim().to_lowercase();
+    let arguments: Vec<&str> = color[open + 1..color.len() - 1]
+        .split(|c: char| c == ',' || c == '/' || c.is_whitespace())
+        .filter(|argument| !argument.is_empty())
+        .collect();
+    let alpha = match arguments.len() {
+        3 => 1.0,
+        4 => parse_number(arguments[3], 1.0)?,
+        _ => return None,
+    };
+    let (red, green, blue) = match name.as_str() {
+        "rgb" | "rgba" => (parse_number(arguments[0], 255.0)?,
+                           parse_number(arguments[1], 255.0)?,
+                           parse_number(arguments[2], 255.0)?),
+        "hsl" | "hsla" => {
+            let hue = arguments[0].trim_end_matches("deg").parse::<f64>().ok()?;
+            let saturation = parse_number(arguments[1], 1.0)?;
+            let lightness = parse_number(arguments[2], 1.0)?;
+            hsl_to_rgb(hue, saturation.max(0.0).min(1.0), lightness.max(0.0).min(1.0))
+        },
+        _ => return None,
+    };
+    let channel = |value: f64| value.round().max(0.0).min(255.0) as u8;
+    Some(Rgba { data: [channel(red), channel(green), channel(blue), channel(alpha * 255.0)] })
+}
+
+/// Converts a hue in degrees, saturation and lightness between 0 and 1 to
+/// red, green and blue between 0 and 255.
+fn hsl_to_rgb(hue: f64, saturation: f64, lightness: f64) -> (f64, f64, f64) {
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let hue = hue.rem_euclid(360.0) / 60.0;
+    let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+    let (red, green, blue) = match hue as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = lightness - chroma / 2.0;
+    ((red + m) * 255.0, (green + m) * 255.0, (blue + m) * 255.0)
+}
+
+/// Looks up a CSS or X11 color name, ignoring case and spaces. X11's
+/// `grayN` from 0 to 100 are computed, where X11 and CSS disagree (e.g.
+/// `gray` and `green`) CSS wins.
+fn named(name: &str) -> Option<Rgba<u8>> {
+    let name: String = name.chars()
+        .filter(|c| !c.is_whitespace())
+        .flat_map(|c| c.to_lowercase())
+        .collect();
+    for prefix in &["gray", "grey"] {
+        if name.starts_with(prefix) && name.len() > prefix.len() {
+            let level = name[prefix.len()..].parse::<u32>().ok().filter(|&level| level <= 100)?;
+            let value = ((level * 255) as f64 / 100.0).round() as u8;
+            return Some(Rgba { data: [value, value, value, 255] })
+        }
+    }
+    NAMES.binary_search_by(|&(candidate, _)| candidate.cmp(&name.as_str()))
+        .ok()
+        .map(|index| from_u32(NAMES[index].1))
+}
+
+/// CSS named colors and the X11 names CSS lacks, sorted by name.
+const NAMES: &'static [(&'static str, u32)] = &[
+    ("aliceblue", 0xf0f8ff),
+    ("antiquewhite", 0xfaebd7),
+    ("aqua", 0x00ffff),
+    ("aquamarine", 0x7fffd4),
+    ("azure", 0xf0ffff),
+    ("beige", 0xf5f5dc),
+    ("bisque", 0xffe4c4),
+    ("black", 0x000000),
+    ("blanchedalmond", 0xffebcd),
+    ("blue", 0x0000ff),
+    ("blueviolet", 0x8a2be2),
+    ("brown", 0xa52a2a),
+    ("burlywood", 0xdeb887),
+    ("cadetblue", 0x5f9ea0),
+    ("chartreuse", 0x7fff00),
+    ("chocolate", 0xd2691e),
+    ("coral", 0xff7f50),
+    ("cornflowerblue", 0x6495ed),
+    ("cornsilk", 0xfff8dc),
+    ("crimson", 0xdc143c),
+    ("cyan", 0x00ffff),
+    ("darkblue", 0x00008b),
+    ("darkcyan", 0x008b8b),
+    ("darkgoldenrod", 0xb8860b),
+    ("darkgray", 0xa9a9a9),
+    ("darkgreen", 0x006400),
+    ("darkgrey", 0xa9a9a9),
+    ("darkkhaki", 0xbdb76b),
+    ("darkmagenta", 0x8b008b),
+    ("darkolivegreen", 0x556b2f),
+    ("darkorange", 0xff8c00),
+    ("darkorchid", 0x9932cc),
+    ("darkred", 0x8b0000),
+    ("darksalmon", 0xe9967a),
+    ("darkseagreen", 0x8fbc8f),
+    ("darkslateblue", 0x483d8b),
+    ("darkslategray", 0x2f4f4f),
+    ("darkslategrey", 0x2f4f4f),
+    ("darkturquoise", 0x00ced1),
+    ("darkviolet", 0x9400d3),
+    ("deeppink", 0xff1493),
+    ("deepskyblue", 0x00bfff),
+    ("dimgray", 0x696969),
+    ("dimgrey", 0x696969),
+    ("dodgerblue", 0x1e90ff),
+    ("firebrick", 0xb22222),
+    ("floralwhite", 0xfffaf0),
+    ("forestgreen", 0x228b22),
+    ("fuchsia", 0xff00ff),
+    ("gainsboro", 0xdcdcdc),
+    ("ghostwhite", 0xf8f8ff),
+    ("gold", 0xffd700),
+    ("goldenrod", 0xdaa520),
+    ("gray", 0x808080),
+    ("green", 0x008000),
+    ("greenyellow", 0xadff2f),
+    ("grey", 0x808080),
+    ("honeydew", 0xf0fff0),
+    ("hotpink", 0xff69b4),
+    ("indianred", 0xcd5c5c),
+    ("indigo", 0x4b0082),
+    ("ivory", 0xfffff0),
+    ("khaki", 0xf0e68c),
+    ("lavender", 0xe6e6fa),
+    ("lavenderblush", 0xfff0f5),
+    ("lawngreen", 0x7cfc00),
+    ("lemonchiffon", 0xfffacd),
+    ("lightblue", 0xadd8e6),
+    ("lightcoral", 0xf08080),
+    ("lightcyan", 0xe0ffff),
+    ("lightgoldenrod", 0xeedd82),
+    ("lightgoldenrodyellow", 0xfafad2),
+    ("lightgray", 0xd3d3d3),
+    ("lightgreen", 0x90ee90),
+    ("lightgrey", 0xd3d3d3),
+    ("lightpink", 0xffb6c1),
+    ("lightsalmon", 0xffa07a),
+    ("lightseagreen", 0x20b2aa),
+    ("lightskyblue", 0x87cefa),
+    ("lightslateblue", 0x8470ff),
+    ("lightslategray", 0x778899),
+    ("lightslategrey", 0x778899),
+    ("lightsteelblue", 0xb0c4de),
+    ("lightyellow", 0xffffe0),
+    ("lime", 0x00ff00),
+    ("limegreen", 0x32cd32),
+    ("linen", 0xfaf0e6),
+    ("magenta", 0xff00ff),
+    ("maroon", 0x800000),
+    ("mediumaquamarine", 0x66cdaa),
+    ("mediumblue", 0x0000cd),
+    ("mediumorchid", 0xba55d3),
+    ("mediumpurple", 0x9370db),
+    ("mediumseagreen", 0x3cb371),
+    ("mediumslateblue", 0x7b68ee),
+    ("mediumspringgreen", 0x00fa9a),
+    ("mediumturquoise", 0x48d1cc),
+    ("mediumvioletred", 0xc71585),
+    ("midnightblue", 0x191970),
+    ("mintcream", 0xf5fffa),
+    ("mistyrose", 0xffe4e1),
+    ("moccasin", 0xffe4b5),
+    ("navajowhite", 0xffdead),
+    ("navy", 0x000080),
+    ("navyblue", 0x000080),
+    ("oldlace", 0xfdf5e6),
+    ("olive", 0x808000),
+    ("olivedrab", 0x6b8e23),
+    ("orange", 0xffa500),
+    ("orangered", 0xff4500),
+    ("orchid", 0xda70d6),
+    ("palegoldenrod", 0xeee8aa),
+    ("palegreen", 0x98fb98),
+    ("paleturquoise", 0xafeeee),
+    ("palevioletred", 0xdb7093),
+    ("papayawhip", 0xffefd5),
+    ("peachpuff", 0xffdab9),
+    ("peru", 0xcd853f),
+    ("pink", 0xffc0cb),
+    ("plum", 0xdda0dd),
+    ("powderblue", 0xb0e0e6),
+    ("purple", 0x800080),
+    ("rebeccapurple", 0x663399),
+    ("red", 0xff0000),
+    ("rosybrown", 0xbc8f8f),
+    ("royalblue", 0x4169e1),
+    ("saddlebrown", 0x8b4513),
+    ("salmon", 0xfa8072),
+    ("sandybrown", 0xf4a460),
+    ("seagreen", 0x2e8b57),
+    ("seashell", 0xfff5ee),
+    ("sienna", 0xa0522d),
+    ("silver", 0xc0c0c0),
+    ("skyblue", 0x87ceeb),
+    ("slateblue", 0x6a5acd),
+    ("slategray", 0x708090),
+    ("slategrey", 0x708090),
+    ("snow", 0xfffafa),
+    ("springgreen", 0x00ff7f),
+    ("steelblue", 0x4682b4),
+    ("tan", 0xd2b48c),
+    ("teal", 0x008080),
+    ("thistle", 0xd8bfd8),
+    ("tomato", 0xff6347),
+    ("turquoise", 0x40e0d0),
+    ("violet", 0xee82ee),
+    ("violetred", 0xd02090),
+    ("wheat", 0xf5deb3),
+    ("white", 0xffffff),
+    ("whitesmoke", 0xf5f5f5),
+    ("yellow", 0xffff00),
+    ("yellowgreen", 0x9acd32),
+];
+
+#[test]
+fn test_parse() {
+    let rgba = |r, g, b, a| Ok(Rgba { data: [r, g, b, a] });
+    assert_eq!(parse("1a1a2e"), rgba(0x1a, 0x1a, 0x2e, 255));
+    assert_eq!(parse("255"), rgba(0, 0, 255, 255));
+    assert_eq!(parse("#fa0"), rgba(255, 170, 0, 255));
+    assert_eq!(parse("#1a1a2e80"), rgba(0x1a, 0x1a, 0x2e, 0x80));
+    assert_eq!(parse("rgb(255, 0, 50%)"), rgba(255, 0, 128, 255));
+    assert_eq!(parse("rgba(10 20 30 / 0.5)"), rgba(10, 20, 30, 128));
+    assert_eq!(parse("hsl(120, 100%, 25%)"), rgba(0, 128, 0, 255));
+    assert_eq!(parse("Light Goldenrod"), rgba(0xee, 0xdd, 0x82, 255));
+    assert_eq!(parse("grey50"), rgba(128, 128, 128, 255));
+    assert!(parse("#12345").is_err());
+    assert!(parse("blurple").is_err());
+    assert!(parse("rgb(1, 2)").is_err());
+    assert_eq!(split_list("red, rgb(0, 0, 255),#fff"), vec!["red", "rgb(0, 0, 255)", "#fff"]);
+    assert!(NAMES.windows(2).all(|pair| pair[0].0 < pair[1].0));
+}
@@ -0,0 +1,117 @@
+//! The configuration file given with `--config`.
+//!
+//! Every line sets a command line option by its long name, options that
+//! take no value are given alone. Empty lines and lines starting with `#`
+//! are ignored:
+//!
+//! ```text
+//! image = /home/me/Pictures/wallpaper.jpg
+//! mode = fill
+//! filter = blur:4
+//! filter = dim:0.3
+//! transparent
+//! ```
+//!
+//! Options given on the command line replace the same options from the file.
+
+use std::fs::File;
+use std::io::Read;
+
+/// An option from the file and its value, if it has one.
+pub type Entry = (String, Option<String>);
+
+/// Reads the options from the configuration file at `path`.
+pub fn read(path: &str) -> Result<Vec<Entry>, String> {
+    let mut contents = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut contents))
+        .map_err(|err| format!("Could not read config file {}: {}", path, err))?;
+    Ok(parse(&contents))
+}
+
+fn parse(contents: &str) -> Vec<Entry> {
+    contents.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap_or("").trim().to_string();
+            let value = parts.next().map(|value| value.trim().to_string());
+            (key, value)
+        })
+        .collect()
+}
+
+/// Turns the entries back into command line arguments.
+pub fn to_args<I>(entries: I) -> Vec<String>
+    where I: IntoIterator<Item = Entry>
+{
+    let mut args = Vec::new();
+    for (key, value) in entries {
+        args.push(format!("--{}", key));
+        args.extend(value);
+    }
+    args
+}
+
+/// The value of `--config` in the command line arguments, if given.
+pub fn path_from_args(args: &[String]) -> Option<String> {
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return args.next().cloned()
+        } else if arg.starts_with("--config=") {
+            return Some(arg["--config=".len()..].to_string())
+        }
+    }
+    None
+}
+
+/// Whether the option `long` appears in the command line arguments, either
+/// by its long name or by the short form listed for it in `shorts`, before
+/// a `--` that ends the options. The short forms take a value, which may be
+/// attached to them, e.g. `-cred`.
+pub fn given_in_args(args: &[String], long: &str, shorts: &[(&str, &str)]) -> bool {
+    let short = shorts.iter()
+        .find(|&&(_, name)| name == long)
+        .map(|&(short, _)| format!("-{}", short));
+    let long = format!("--{}", long);
+    let given = |arg: &str| {
+        arg == long || arg.starts_with(&long) && arg[long.len()..].starts_with('=')
+            || short.as_ref().map_or(false, |short| arg.starts_with(short.as_str()))
+    };
+    args.iter().skip(1)
+        .take_while(|arg| *arg != "--")
+        .any(|arg| given(arg))
+}
+
+#[test]
+fn test_parse() {
+    let entries = parse("# Comment\n\n  image = /tmp/a b.png \nfilter=blur:4\ntransparent\n");
+    assert_eq!(entries, vec![("image".to_string(), Some("/tmp/a b.png".to_string())),
+                             ("filter".to_string(), Some("blur:4".to_string())),
+                             ("transparent".to_string(), None)]);
+    assert_eq!(to_args(entries), vec!["--image", "/tmp/a b.png", "--filter", "blur:4",
+                                      "--transparent"]);
+}
+
+#[test]
+fn test_given_in_args() {
+    let shorts = &[("c", "color"), ("f", "image")];
+    let args = |args: &[&str]| -> Vec<String> {
+        ::std::iter::once("wc-bg").chain(args.iter().cloned()).map(String::from).collect()
+    };
+    assert!(given_in_args(&args(&["--color", "red"]), "color", shorts));
+    assert!(given_in_args(&args(&["--color=red"]), "color", shorts));
+    assert!(given_in_args(&args(&["-c", "red"]), "color", shorts));
+    assert!(given_in_args(&args(&["-c=red"]), "color", shorts));
+    assert!(given_in_args(&args(&["-cred"]), "color", shorts));
+    assert!(given_in_args(&args(&["-f/tmp/a.png"]), "image", shorts));
+    // The option name has to match completely.
+    assert!(!given_in_args(&args(&["--colors", "red"]), "color", shorts));
+    assert!(!given_in_args(&args(&["--mode", "fill"]), "color", shorts));
+    // Neither the program name nor anything after `--` is an option.
+    assert!(!given_in_args(&["-c".to_string()], "color", shorts));
+    assert!(!given_in_args(&args(&["--", "-c"]), "color", shorts));
+    assert_eq!(path_from_args(&args(&["--config=/tmp/bg.conf"])), Some("/tmp/bg.conf".into()));
+}
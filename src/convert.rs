@@ -0,0 +1,276 @@
+//! Conversion of decoded RGBA pixels into the layouts of `wl_shm` buffers,
+//! premultiplied ARGB8888 and the alternatives `shm` picks.
+
+use dither::Dither;
+use precise::PreciseImage;
+
+/// Number of pixels converted per block. Working on a fixed-size block gives
+/// the inner loop a constant trip count, which lets the compiler unroll and
+/// vectorize it.
+const BLOCK_PIXELS: usize = 4;
+const BLOCK_BYTES: usize = BLOCK_PIXELS * 4;
+
+/// Divides a product of two `u8`s by 255, rounding to the nearest integer.
+///
+/// The add-and-shift form is exact for every product of two `u8`s and avoids
+/// an integer division per channel.
+#[inline(always)]
+fn div_255(x: u32) -> u8 {
+    let x = x + 128;
+    ((x + (x >> 8)) >> 8) as u8
+}
+
+/// Premultiplies a single color channel by `alpha`, rounding to the nearest
+/// value instead of truncating (which darkens semi-transparent edges).
+#[inline(always)]
+pub fn premultiply(channel: u8, alpha: u8) -> u8 {
+    div_255(channel as u32 * alpha as u32)
+}
+
+#[inline(always)]
+fn convert_pixel(pixel: &mut [u8]) {
+    let alpha = pixel[3];
+    let red = premultiply(pixel[0], alpha);
+    let green = premultiply(pixel[1], alpha);
+    let blue = premultiply(pixel[2], alpha);
+    pixel[0] = blue;
+    pixel[1] = green;
+    pixel[2] = red;
+}
+
+#[inline(always)]
+fn convert_block(block: &mut [u8]) {
+    let mut out = [0u8; BLOCK_BYTES];
+    for i in 0..BLOCK_PIXELS {
+        let p = i * 4;
+        let alpha = block[p + 3] as u32;
+        out[p] = div_255(block[p + 2] as u32 * alpha);
+        out[p + 1] = div_255(block[p + 1] as u32 * alpha);
+        out[p + 2] = div_255(block[p] as u32 * alpha);
+        out[p + 3] = alpha as u8;
+    }
+    block.copy_from_slice(&out);
+}
+
+/// Premultiplies every pixel of a tightly packed RGBA buffer by its alpha
+/// and swaps the red and blue channels, in place.
+///
+/// The result is the little-endian byte order of `WlShmFormat::Argb8888`.
+pub fn rgba_to_argb_premultiplied(buf: &mut [u8]) {
+    assert_eq!(buf.len() % 4, 0, "buffer is not made of whole RGBA pixels");
+    let mut blocks = buf.chunks_exact_mut(BLOCK_BYTES);
+    for block in &mut blocks {
+        convert_block(block);
+    }
+    for pixel in blocks.into_remainder().chunks_exact_mut(4) {
+        convert_pixel(pixel);
+    }
+}
+
+/// Swaps the red and blue channels of a tightly packed RGBA buffer in
+/// place, giving `WlShmFormat::Xrgb8888`. Alpha is ignored by the format,
+/// so it is only set to opaque.
+pub fn rgba_to_xrgb(buf: &mut [u8]) {
+    for pixel in buf.chunks_exact_mut(4) {
+        pixel.swap(0, 2);
+        pixel[3] = 255;
+    }
+}
+
+/// Converts an opaque image, before it was rounded to 8 bits, to
+/// `WlShmFormat::Xrgb2101010`.
+pub fn precise_to_xrgb2101010(image: &PreciseImage) -> Vec<u8> {
+    let mut out = Vec::with_capacity(image.len());
+    for pixel in image.pixels() {
+        let channel = |c: f32| (c / 255.0 * 1023.0).round().max(0.0).min(1023.0) as u32;
+        push_2101010(&mut out, channel(pixel[0]), channel(pixel[1]), channel(pixel[2]));
+    }
+    out
+}
+
+/// Converts a tightly packed RGBA buffer of an opaque image to
+/// `WlShmFormat::Xrgb2101010`, widening the 8 bit channels.
+pub fn rgba_to_xrgb2101010(buf: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(buf.len());
+    for pixel in buf.chunks_exact(4) {
+        let channel = |c: u8| (c as u32 * 1023 + 127) / 255;
+        push_2101010(&mut out, channel(pixel[0]), channel(pixel[1]), channel(pixel[2]));
+    }
+    out
+}
+
+/// Appends an opaque 2-10-10-10 pixel in little endian byte order.
+fn push_2101010(out: &mut Vec<u8>, red: u32, green: u32, blue: u32) {
+    let value = 3 << 30 | red << 20 | green << 10 | blue;
+    out.extend_from_slice(&[value as u8, (value >> 8) as u8,
+                            (value >> 16) as u8, (value >> 24) as u8]);
+}
+
+/// Converts a tightly packed RGBA buffer of an opaque image `width` pixels
+/// wide to `WlShmFormat::Rgb565`, rounding with `dither`.
+pub fn rgba_to_rgb565(buf: &[u8], width: u32, dither: Dither) -> Vec<u8> {
+    let mut out = Vec::with_capacity(buf.len() / 2);
+    for (i, pixel) in buf.chunks_exact(4).enumerate() {
+        let (x, y) = (i as u32 % width, i as u32 / width);
+        let channel = |c: u8, max: f32| dither.quantize(c as f32 * max / 255.0, x, y) as u16;
+        let value = channel(pixel[0], 31.0) << 11 | channel(pixel[1], 63.0) << 5
+            | channel(pixel[2], 31.0);
+        out.extend_from_slice(&[value as u8, (value >> 8) as u8]);
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::{load_from_memory, Pixel};
+
+    /// The straightforward per-pixel conversion, with the rounding done in
+    /// floating point.
+    fn scalar_reference(buf: &mut [u8]) {
+        for pixel in buf.chunks_mut(4) {
+            let alpha = pixel[3] as f64;
+            let convert = |c: u8| (c as f64 * alpha / 255.0).round() as u8;
+            let (red, green, blue) = (pixel[0], pixel[1], pixel[2]);
+            pixel[0] = convert(blue);
+            pixel[1] = convert(green);
+            pixel[2] = convert(red);
+        }
+    }
+
+    #[test]
+    fn test_premultiply() {
+        assert_eq!(premultiply(10, 254), 10);
+        assert_eq!(premultiply(2, 255), 2);
+        assert_eq!(premultiply(255, 255), 255);
+        assert_eq!(premultiply(255, 0), 0);
+        assert_eq!(premultiply(255, 128), 128);
+        assert_eq!(premultiply(1, 128), 1);
+    }
+
+    #[test]
+    fn premultiply_is_rounded_for_all_inputs() {
+        for channel in 0..256u32 {
+            for alpha in 0..256u32 {
+                let expected = (channel as f64 * alpha as f64 / 255.0).round();
+                assert_eq!(premultiply(channel as u8, alpha as u8) as f64, expected,
+                           "channel = {}, alpha = {}", channel, alpha);
+            }
+        }
+    }
+
+    #[test]
+    fn matches_scalar_reference() {
+        // Every channel/alpha pair, in a buffer whose length is not a
+        // multiple of the block size so the remainder path runs too.
+        let mut buf = Vec::with_capacity(256 * 256 * 4 + 4);
+        for alpha in 0..256u32 {
+            for value in 0..256u32 {
+                buf.extend_from_slice(&[value as u8,
+                                        (255 - value) as u8,
+                                        (value * 7) as u8,
+                                        alpha as u8]);
+            }
+        }
+        buf.extend_from_slice(&[200, 100, 50, 128]);
+        let mut expected = buf.clone();
+        scalar_reference(&mut expected);
+        rgba_to_argb_premultiplied(&mut buf);
+        assert_eq!(buf, expected);
+    }
+
+    #[test]
+    fn alternate_formats() {
+        let buf = [255, 0, 128, 255, 255, 255, 255, 0];
+        let mut xrgb = buf.to_vec();
+        rgba_to_xrgb(&mut xrgb);
+        assert_eq!(xrgb, vec![128, 0, 255, 255, 255, 255, 255, 255]);
+
+        assert_eq!(rgba_to_xrgb2101010(&buf),
+                   vec![0x02, 0x02, 0xf0, 0xff, 0xff, 0xff, 0xff, 0xff]);
+
+        assert_eq!(rgba_to_rgb565(&buf, 2, Dither::None), vec![0x10, 0xf8, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn precise_xrgb2101010() {
+        use image::{ImageBuffer, Rgba};
+
+        // Values between two 8 bit steps keep their own 10 bit value.
+        let image = ImageBuffer::from_fn(3, 1, |x, _| {
+            let value = [128.0, 128.25, 128.5][x as usize];
+            Rgba { data: [value, 0.0, 255.0, 255.0] }
+        });
+        let reds: Vec<u32> = precise_to_xrgb2101010(&image).chunks(4)
+            .map(|pixel| pixel[2] as u32 >> 4 | (pixel[3] as u32 & 0x3f) << 4)
+            .collect();
+        assert_eq!(reds, vec![514, 515, 516]);
+        assert_eq!(precise_to_xrgb2101010(&image)[..4], rgba_to_xrgb2101010(&[128, 0, 255, 255])[..]);
+    }
+
+    #[test]
+    fn cursor_matches_unpremultiplied_source() {
+        let source = load_from_memory(include_bytes!("../assets/arrow.png"))
+            .expect("Could not read cursor data")
+            .to_rgba();
+        let mut converted = source.clone().into_vec();
+        rgba_to_argb_premultiplied(&mut converted);
+        for (pixel, argb) in source.pixels().zip(converted.chunks(4)) {
+            let rgba = pixel.channels();
+            let alpha = rgba[3] as f64;
+            for &(src, dst) in &[(0, 2), (1, 1), (2, 0)] {
+                let exact = rgba[src] as f64 * alpha / 255.0;
+                assert!((argb[dst] as f64 - exact).abs() <= 0.5,
+                        "{:?} converted to {:?}", rgba, argb);
+            }
+            assert_eq!(argb[3], rgba[3]);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "unstable"))]
+mod bench {
+    extern crate test;
+
+    use self::test::Bencher;
+    use super::*;
+
+    fn rgba_conversion(num: u8, third_num: u32) -> u8 {
+        let big_num = num as u32;
+        ((big_num * third_num) / 255) as u8
+    }
+
+    fn frame() -> Vec<u8> {
+        (0..1920 * 1080 * 4).map(|i| (i * 31) as u8).collect()
+    }
+
+    #[bench]
+    fn bench_block_1080p(b: &mut Bencher) {
+        let source = frame();
+        let mut buf = source.clone();
+        b.iter(|| {
+            buf.copy_from_slice(&source);
+            rgba_to_argb_premultiplied(&mut buf);
+        });
+    }
+
+    /// The per-pixel loop the background and cursor used to run.
+    #[bench]
+    fn bench_scalar_1080p(b: &mut Bencher) {
+        let source = frame();
+        let mut buf = source.clone();
+        b.iter(|| {
+            buf.copy_from_slice(&source);
+            for pixel in buf.chunks_mut(4) {
+                let alpha = pixel[3] as u32;
+                pixel[0] = rgba_conversion(pixel[0], alpha);
+                pixel[1] = rgba_conversion(pixel[1], alpha);
+                pixel[2] = rgba_conversion(pixel[2], alpha);
+
+                let tmp = pixel[2];
+                pixel[2] = pixel[0];
+                pixel[0] = tmp;
+            }
+        });
+    }
+}
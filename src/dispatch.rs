@@ -0,0 +1,53 @@
+//! Dispatching Wayland events with a deadline, so the main loop can also
+//! wake up for timers and signals.
+
+use std::io;
+use std::os::unix::io::RawFd;
+use std::time::{Duration, Instant};
+
+use libc;
+use wayland_client::EventQueue;
+use wayland_client::protocol::wl_display::WlDisplay;
+
+/// Dispatches events, waiting until some arrive, `wake` becomes readable
+/// or `deadline` passes. Without a deadline this waits as long as it takes.
+pub fn dispatch_until(display: &WlDisplay,
+                      event_queue: &mut EventQueue,
+                      deadline: Option<Instant>,
+                      wake: RawFd) -> io::Result<()> {
+    // Events that have been read but not dispatched yet have to be handled
+    // before reading again.
+    let guard = match event_queue.prepare_read() {
+        Some(guard) => guard,
+        None => return event_queue.dispatch_pending().map(|_| ())
+    };
+    display.flush()?;
+
+    let timeout = match deadline {
+        Some(deadline) => {
+            let now = Instant::now();
+            let timeout = if deadline > now { deadline - now } else { Duration::from_secs(0) };
+            // Round up, so we don't wake up just before the deadline.
+            let millis = timeout.as_secs() * 1000 + (timeout.subsec_nanos() as u64 + 999_999) / 1_000_000;
+            millis.min(i32::max_value() as u64) as libc::c_int
+        },
+        None => -1
+    };
+    let mut fds = [
+        libc::pollfd { fd: display.get_fd(), events: libc::POLLIN, revents: 0 },
+        libc::pollfd { fd: wake, events: libc::POLLIN, revents: 0 },
+    ];
+    let ready = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) };
+    if ready > 0 && fds[0].revents != 0 {
+        guard.read_events()?;
+    } else {
+        guard.cancel();
+        if ready < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err)
+            }
+        }
+    }
+    event_queue.dispatch_pending().map(|_| ())
+}
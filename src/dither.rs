@@ -0,0 +1,184 @@
+//! Dithering of high precision values down to 8 bits, so that smooth
+//! gradients don't show bands.
+//!
+//! Images lose their extra precision wherever they are rounded to 8 bits,
+//! so the dither is applied there: once when the finished background is
+//! rounded (see `precise`), when images with more than 8 bits are decoded
+//! (see `hdr`) and when buffers are converted to fewer bits (see `shm`).
+
+use std::str::FromStr;
+
+/// Side of the blue noise texture, which is tiled over the output.
+const BLUE_NOISE_SIZE: usize = 32;
+
+thread_local! {
+    static BLUE_NOISE: Vec<f32> = blue_noise();
+}
+
+/// How values are rounded to 8 bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dither {
+    /// Round to the nearest value.
+    None,
+
+    /// An 8x8 Bayer matrix. Cheap, but the cross-hatch pattern can be seen
+    /// on large flat areas.
+    Ordered,
+
+    /// A blue noise texture, which has no visible pattern.
+    BlueNoise,
+}
+
+impl Default for Dither {
+    fn default() -> Dither {
+        Dither::None
+    }
+}
+
+impl FromStr for Dither {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Dither, String> {
+        match s {
+            "none"       => Ok(Dither::None),
+            "ordered"    => Ok(Dither::Ordered),
+            "blue-noise" => Ok(Dither::BlueNoise),
+            _            => Err(format!("Invalid dither: {}", s)),
+        }
+    }
+}
+
+impl Dither {
+    /// The threshold at a pixel, between -0.5 and 0.5.
+    fn offset(&self, x: u32, y: u32) -> f32 {
+        match *self {
+            Dither::None => 0.0,
+            Dither::Ordered => bayer(x, y),
+            Dither::BlueNoise => BLUE_NOISE.with(|noise| {
+                let (x, y) = (x as usize % BLUE_NOISE_SIZE, y as usize % BLUE_NOISE_SIZE);
+                noise[y * BLUE_NOISE_SIZE + x]
+            }),
+        }
+    }
+
+    /// Rounds a value between 0 and 255 to 8 bits, dithered by its position
+    /// so that the average over an area keeps the precision.
+    pub fn quantize(&self, value: f32, x: u32, y: u32) -> u8 {
+        (value + self.offset(x, y)).round().max(0.0).min(255.0) as u8
+    }
+}
+
+/// The threshold of an 8x8 ordered (Bayer) dither at a pixel, evenly spread
+/// between -0.5 and 0.5.
+fn bayer(x: u32, y: u32) -> f32 {
+    let mut index = 0;
+    // Interleaves the bits of x ^ y and y, in reverse.
+    for bit in 0..3 {
+        index = index << 2 | ((x ^ y) >> bit & 1) << 1 | (y >> bit & 1);
+    }
+    (index as f32 + 0.5) / 64.0 - 0.5
+}
+
+/// Generates a tileable blue noise texture with Ulichney's void and cluster
+/// method, as thresholds between -0.5 and 0.5.
+fn blue_noise() -> Vec<f32> {
+    const SIZE: usize = BLUE_NOISE_SIZE;
+    const CELLS: usize = SIZE * SIZE;
+    const SIGMA: f32 = 1.5;
+
+    // The gaussian each pixel adds to the energy of the others, wrapping
+    // around the edges.
+    let mut kernel = vec![0f32; CELLS];
+    for dy in 0..SIZE {
+        for dx in 0..SIZE {
+            let distance = |d: usize| d.min(SIZE - d) as f32;
+            let (fx, fy) = (distance(dx), distance(dy));
+            kernel[dy * SIZE + dx] = (-(fx * fx + fy * fy) / (2.0 * SIGMA * SIGMA)).exp();
+        }
+    }
+    let update = |energy: &mut Vec<f32>, cell: usize, sign: f32| {
+        let (cx, cy) = (cell % SIZE, cell / SIZE);
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                let (dx, dy) = ((x + SIZE - cx) % SIZE, (y + SIZE - cy) % SIZE);
+                energy[y * SIZE + x] += sign * kernel[dy * SIZE + dx];
+            }
+        }
+    };
+    // The set pixel with the most energy, or the unset one with the least.
+    let tightest_cluster = |set: &[bool], energy: &[f32]| (0..CELLS)
+        .filter(|&cell| set[cell])
+        .max_by(|&a, &b| energy[a].partial_cmp(&energy[b]).unwrap())
+        .unwrap();
+    let largest_void = |set: &[bool], energy: &[f32]| (0..CELLS)
+        .filter(|&cell| !set[cell])
+        .min_by(|&a, &b| energy[a].partial_cmp(&energy[b]).unwrap())
+        .unwrap();
+
+    // A random pattern, spread out evenly by moving pixels from the
+    // tightest cluster into the largest void until that is the same one.
+    // Some patterns never settle, so the moves are limited.
+    let mut seed = 0x2545_f491u32;
+    let mut set = vec![false; CELLS];
+    let mut energy = vec![0f32; CELLS];
+    let mut count = 0;
+    while count < CELLS / 10 {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        let cell = seed as usize % CELLS;
+        if !set[cell] {
+            set[cell] = true;
+            update(&mut energy, cell, 1.0);
+            count += 1;
+        }
+    }
+    for _ in 0..CELLS {
+        let cluster = tightest_cluster(&set, &energy);
+        set[cluster] = false;
+        update(&mut energy, cluster, -1.0);
+        let void = largest_void(&set, &energy);
+        set[void] = true;
+        update(&mut energy, void, 1.0);
+        if void == cluster {
+            break
+        }
+    }
+
+    // Ranks the pixels of the initial pattern by removing clusters, then
+    // the rest by filling voids.
+    let mut rank = vec![0usize; CELLS];
+    let (initial_set, initial_energy) = (set.clone(), energy.clone());
+    for index in (0..count).rev() {
+        let cluster = tightest_cluster(&set, &energy);
+        set[cluster] = false;
+        update(&mut energy, cluster, -1.0);
+        rank[cluster] = index;
+    }
+    set = initial_set;
+    energy = initial_energy;
+    for index in count..CELLS {
+        let void = largest_void(&set, &energy);
+        set[void] = true;
+        update(&mut energy, void, 1.0);
+        rank[void] = index;
+    }
+    rank.iter().map(|&rank| (rank as f32 + 0.5) / CELLS as f32 - 0.5).collect()
+}
+
+#[test]
+fn test_quantize() {
+    assert_eq!((0..8).map(|x| (bayer(x, 0) * 64.0 + 31.5) as u32).collect::<Vec<_>>(),
+               vec![0, 32, 8, 40, 2, 34, 10, 42]);
+    // A quarter of the way between 100 and 101 averages out right.
+    let ordered = Dither::Ordered;
+    let sum: u32 = (0..8).flat_map(|y| (0..8).map(move |x| ordered.quantize(100.25, x, y) as u32)).sum();
+    assert_eq!(sum, 64 * 100 + 16);
+    assert_eq!(ordered.quantize(255.4, 7, 7), 255);
+
+    // Every threshold of the blue noise is used exactly once.
+    let mut noise = blue_noise();
+    noise.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let cells = noise.len() as f32;
+    assert!(noise.iter().enumerate().all(|(i, &t)| t == (i as f32 + 0.5) / cells - 0.5));
+}
@@ -0,0 +1,151 @@
+//! Just enough of an EXIF reader to find the orientation of a JPEG.
+//!
+//! Cameras and phones store photos in the sensor's orientation and record
+//! how to display them in the EXIF Orientation tag, which `image` ignores.
+
+use image::imageops::{flip_horizontal, flip_vertical, rotate180, rotate270, rotate90};
+
+use precise::PreciseImage;
+
+const ORIENTATION_TAG: u16 = 0x0112;
+
+/// How the stored pixels have to be transformed to display the image
+/// upright, as recorded in the EXIF Orientation tag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+    Normal,
+    FlipHorizontal,
+    Rotate180,
+    FlipVertical,
+    Transpose,
+    Rotate90,
+    Transverse,
+    Rotate270,
+}
+
+impl Orientation {
+    fn from_tag(value: u16) -> Option<Orientation> {
+        match value {
+            1 => Some(Orientation::Normal),
+            2 => Some(Orientation::FlipHorizontal),
+            3 => Some(Orientation::Rotate180),
+            4 => Some(Orientation::FlipVertical),
+            5 => Some(Orientation::Transpose),
+            6 => Some(Orientation::Rotate90),
+            7 => Some(Orientation::Transverse),
+            8 => Some(Orientation::Rotate270),
+            _ => None,
+        }
+    }
+
+    /// Transforms `image` so that it is displayed upright.
+    pub fn apply(self, image: PreciseImage) -> PreciseImage {
+        match self {
+            Orientation::Normal         => image,
+            Orientation::FlipHorizontal => flip_horizontal(&image),
+            Orientation::Rotate180      => rotate180(&image),
+            Orientation::FlipVertical   => flip_vertical(&image),
+            Orientation::Transpose      => flip_horizontal(&rotate90(&image)),
+            Orientation::Rotate90       => rotate90(&image),
+            Orientation::Transverse     => flip_horizontal(&rotate270(&image)),
+            Orientation::Rotate270      => rotate270(&image),
+        }
+    }
+}
+
+/// Reads the orientation from the EXIF data of a JPEG file.
+///
+/// Returns `None` if the data is not a JPEG, has no EXIF data or the tag is
+/// missing or malformed.
+pub fn jpeg_orientation(data: &[u8]) -> Option<Orientation> {
+    jpeg_segments(data, 0xE1).into_iter()
+        .find(|segment| segment.starts_with(b"Exif\0\0"))
+        .and_then(|segment| tiff_orientation(&segment[6..]))
+}
+
+/// The contents of the metadata segments of a JPEG file with the `marker`,
+/// e.g. 0xE1 for APP1. Stops at the first malformed segment.
+pub fn jpeg_segments(data: &[u8], marker: u8) -> Vec<&[u8]> {
+    let mut segments = Vec::new();
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return segments
+    }
+    let mut pos = 2;
+    while pos + 4 <= data.len() && data[pos] == 0xFF {
+        // Start of scan or end of image: no metadata follows.
+        if data[pos + 1] == 0xDA || data[pos + 1] == 0xD9 {
+            break
+        }
+        let segment = read_u16(&data[pos + 2..], true)
+            .and_then(|length| data.get(pos + 4..pos + 2 + length as usize));
+        let segment = match segment {
+            Some(segment) => segment,
+            None => break,
+        };
+        if data[pos + 1] == marker {
+            segments.push(segment);
+        }
+        pos += 4 + segment.len();
+    }
+    segments
+}
+
+/// Looks up the orientation tag in the first IFD of a TIFF structure.
+fn tiff_orientation(tiff: &[u8]) -> Option<Orientation> {
+    let big_endian = match tiff.get(0..2)? {
+        b"MM" => true,
+        b"II" => false,
+        _ => return None,
+    };
+    if read_u16(&tiff[2..], big_endian)? != 42 {
+        return None
+    }
+    let ifd = read_u32(&tiff[4..], big_endian)? as usize;
+    let count = read_u16(tiff.get(ifd..)?, big_endian)? as usize;
+    for i in 0..count {
+        let entry = tiff.get(ifd + 2 + i * 12..ifd + 14 + i * 12)?;
+        if read_u16(entry, big_endian)? == ORIENTATION_TAG {
+            // A single SHORT, stored in the first bytes of the value field.
+            return Orientation::from_tag(read_u16(&entry[8..], big_endian)?)
+        }
+    }
+    None
+}
+
+fn read_u16(data: &[u8], big_endian: bool) -> Option<u16> {
+    let bytes = data.get(0..2)?;
+    Some(if big_endian {
+        (bytes[0] as u16) << 8 | bytes[1] as u16
+    } else {
+        (bytes[1] as u16) << 8 | bytes[0] as u16
+    })
+}
+
+fn read_u32(data: &[u8], big_endian: bool) -> Option<u32> {
+    let high = read_u16(data, big_endian)? as u32;
+    let low = read_u16(data.get(2..)?, big_endian)? as u32;
+    Some(if big_endian { high << 16 | low } else { low << 16 | high })
+}
+
+#[test]
+fn test_jpeg_orientation() {
+    fn jpeg(tiff: &[u8]) -> Vec<u8> {
+        let mut data = vec![0xFF, 0xD8, 0xFF, 0xE1];
+        let length = 2 + 6 + tiff.len();
+        data.extend_from_slice(&[(length >> 8) as u8, length as u8]);
+        data.extend_from_slice(b"Exif\0\0");
+        data.extend_from_slice(tiff);
+        data.extend_from_slice(&[0xFF, 0xDA]);
+        data
+    }
+    let motorola = [b'M', b'M', 0, 42, 0, 0, 0, 8,
+                    0, 1,
+                    0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, 6, 0, 0];
+    let intel = [b'I', b'I', 42, 0, 8, 0, 0, 0,
+                 1, 0,
+                 0x12, 0x01, 3, 0, 1, 0, 0, 0, 8, 0, 0, 0];
+    assert_eq!(jpeg_orientation(&jpeg(&motorola)), Some(Orientation::Rotate90));
+    assert_eq!(jpeg_orientation(&jpeg(&intel)), Some(Orientation::Rotate270));
+    assert_eq!(jpeg_orientation(&jpeg(&intel[..12])), None);
+    assert_eq!(jpeg_orientation(include_bytes!("../assets/arrow.png")), None);
+}
@@ -0,0 +1,166 @@
+//! Post-processing of the image after it has been scaled by the mode, to
+//! tone down busy wallpapers so windows stand out.
+//!
+//! Filters are given with `--filter NAME[:ARGS]` and run in the order they
+//! are given, e.g. `--filter blur:4 --filter dim:0.3`.
+
+use image::{imageops, Rgba};
+
+use color;
+use precise::{self, PreciseImage};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    /// Gaussian blur with the given standard deviation, in pixels.
+    Blur(f32),
+
+    /// Darkens the image by a fraction between 0 (unchanged) and 1 (black).
+    Dim(f32),
+
+    /// Adds a value between -255 and 255 to every channel.
+    Brightness(i32),
+
+    /// Changes the contrast by a percentage, negative values reduce it.
+    Contrast(f32),
+
+    /// Scales the saturation, 0 gives a grayscale image and 1 is unchanged.
+    Saturation(f32),
+
+    /// Blends the image towards a color by a fraction between 0 and 1.
+    Tint(Rgba<u8>, f32),
+}
+
+impl Filter {
+    /// Parses a `--filter` value. `tint` uses `default_tint` unless a color
+    /// is given after the amount, which may have colons of its own
+    /// (`wal:color4`).
+    pub fn parse(s: &str, default_tint: Rgba<u8>) -> Result<Filter, String> {
+        let mut parts = s.splitn(3, ':');
+        let name = parts.next().unwrap_or("");
+        let args: Vec<&str> = parts.collect();
+        let arg = |i: usize| args.get(i)
+            .ok_or_else(|| format!("Missing argument for filter '{}'", name));
+        let number = |i: usize| arg(i).and_then(|arg| arg.parse::<f32>()
+            .map_err(|_| format!("Invalid argument for filter '{}': {}", name, arg)));
+        let fraction = |i: usize| number(i).and_then(|value| if value >= 0.0 && value <= 1.0 {
+            Ok(value)
+        } else {
+            Err(format!("Argument for filter '{}' must be between 0 and 1", name))
+        });
+        let filter = match name {
+            "blur" => Filter::Blur(number(0)?),
+            "dim" => Filter::Dim(fraction(0)?),
+            "brightness" => Filter::Brightness(number(0)?.max(-255.0).min(255.0) as i32),
+            "contrast" => Filter::Contrast(number(0)?),
+            "saturation" => Filter::Saturation(number(0)?.max(0.0)),
+            "grayscale" => Filter::Saturation(0.0),
+            "tint" => {
+                let tint = match args.get(1) {
+                    Some(tint) => color::parse(tint)?,
+                    None => default_tint
+                };
+                Filter::Tint(tint, fraction(0)?)
+            },
+            _ => return Err(format!("Unknown filter: {}", name)),
+        };
+        Ok(filter)
+    }
+
+    pub fn apply(&self, image: PreciseImage) -> PreciseImage {
+        match *self {
+            // Premultiplied, so transparent pixels don't darken the edges
+            // they are blurred into.
+            Filter::Blur(sigma) =>
+                precise::unpremultiply(imageops::blur(&precise::premultiply(image), sigma)),
+            Filter::Brightness(value) => map_colors(image, |c, _| c + value as f32),
+            // The same curve as `imageops::contrast`.
+            Filter::Contrast(percent) => {
+                let factor = ((100.0 + percent) / 100.0).powi(2);
+                map_colors(image, |c, _| ((c / 255.0 - 0.5) * factor + 0.5) * 255.0)
+            },
+            Filter::Dim(amount) => map_colors(image, |c, _| c * (1.0 - amount)),
+            Filter::Saturation(factor) => map_pixels(image, |pixel| {
+                let luma = 0.2126 * pixel[0] + 0.7152 * pixel[1] + 0.0722 * pixel[2];
+                for c in 0..3 {
+                    pixel[c] = luma + (pixel[c] - luma) * factor;
+                }
+            }),
+            Filter::Tint(tint, amount) => map_colors(image, |c, i| {
+                c + (tint[i] as f32 - c) * amount
+            }),
+        }
+    }
+}
+
+/// Runs every filter over the image, in order.
+pub fn apply_all(filters: &[Filter], image: PreciseImage) -> PreciseImage {
+    filters.iter().fold(image, |image, filter| filter.apply(image))
+}
+
+/// Maps each color channel (not alpha) of every pixel, given its index.
+fn map_colors<F>(image: PreciseImage, f: F) -> PreciseImage
+    where F: Fn(f32, usize) -> f32
+{
+    map_pixels(image, |pixel| {
+        for c in 0..3 {
+            pixel[c] = f(pixel[c], c);
+        }
+    })
+}
+
+/// Maps the color channels of every pixel, clamping the result.
+fn map_pixels<F>(mut image: PreciseImage, f: F) -> PreciseImage
+    where F: Fn(&mut [f32; 3])
+{
+    for pixel in image.pixels_mut() {
+        let mut channels = [pixel[0], pixel[1], pixel[2]];
+        f(&mut channels);
+        for c in 0..3 {
+            pixel[c] = channels[c].max(0.0).min(255.0);
+        }
+    }
+    image
+}
+
+#[test]
+fn test_parse() {
+    let white = Rgba { data: [255, 255, 255, 255] };
+    assert_eq!(Filter::parse("blur:4", white), Ok(Filter::Blur(4.0)));
+    assert_eq!(Filter::parse("brightness:-300", white), Ok(Filter::Brightness(-255)));
+    assert_eq!(Filter::parse("grayscale", white), Ok(Filter::Saturation(0.0)));
+    assert_eq!(Filter::parse("tint:0.5", white), Ok(Filter::Tint(white, 0.5)));
+    assert_eq!(Filter::parse("tint:0.5:#ff0000", white),
+               Ok(Filter::Tint(Rgba { data: [255, 0, 0, 255] }, 0.5)));
+    assert_eq!(Filter::parse("tint:0.5:rgb(1,2,3)", white),
+               Ok(Filter::Tint(Rgba { data: [1, 2, 3, 255] }, 0.5)));
+    assert!(Filter::parse("dim:1.5", white).is_err());
+    assert!(Filter::parse("blur", white).is_err());
+    assert!(Filter::parse("blur:much", white).is_err());
+    assert!(Filter::parse("sharpen:1", white).is_err());
+}
+
+#[test]
+fn test_parse_palette_tint() {
+    // The whole palette reference after the strength is the color, whatever
+    // the palette holds.
+    let white = Rgba { data: [255, 255, 255, 255] };
+    let expected = color::parse("wal:color4").map(|tint| Filter::Tint(tint, 0.5));
+    assert_eq!(Filter::parse("tint:0.5:wal:color4", white), expected);
+}
+
+#[test]
+fn test_blur_transparent() {
+    use image::ImageBuffer;
+
+    // A white square on a transparent background whose color is black.
+    let image = ImageBuffer::from_fn(16, 16, |x, y| if x >= 4 && x < 12 && y >= 4 && y < 12 {
+        Rgba { data: [255.0, 255.0, 255.0, 255.0] }
+    } else {
+        Rgba { data: [0.0, 0.0, 0.0, 0.0] }
+    });
+    let blurred = Filter::Blur(2.0).apply(image);
+    // The edge fades out without turning gray.
+    let edge = blurred.get_pixel(3, 8);
+    assert!(edge[3] > 0.0 && edge[3] < 255.0);
+    assert!(edge[0] > 254.0, "{:?}", edge);
+}
@@ -0,0 +1,260 @@
+//! Telling image formats apart by their magic bytes, and decoding the ones
+//! the `image` crate doesn't handle.
+
+use std::io::Cursor;
+
+use image::{self, DynamicImage, ImageBuffer, ImageError, ImageFormat, ImageResult, RgbaImage};
+use image_webp::WebPDecoder;
+use png;
+
+use hdr::{self, FloatImage, ToneMapping};
+use pnm;
+use precise::{self, PreciseImage};
+use qoi;
+use svg;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Png,
+    Jpeg,
+    Gif,
+    Bmp,
+    Ico,
+    Tiff,
+    WebP,
+    Avif,
+    Qoi,
+    /// PBM, PGM or PPM.
+    Pnm,
+    Tga,
+    Farbfeld,
+    Svg,
+    /// Radiance HDR.
+    Radiance,
+    OpenExr,
+}
+
+/// A decoded image, before it is brought into the floats backgrounds are
+/// rendered in.
+pub enum Decoded {
+    Image(DynamicImage),
+
+    /// More than 8 bits per channel, sRGB encoded between 0 and 1.
+    Deep(FloatImage),
+
+    /// High dynamic range in linear light, may be brighter than 1.
+    Hdr(FloatImage),
+}
+
+impl Decoded {
+    /// Widens 8 bit images, keeps the precision of deep ones and tone maps
+    /// high dynamic range ones. Nothing is rounded, that is left to
+    /// `render::finish`.
+    pub fn into_precise(self, tone_mapping: &ToneMapping) -> PreciseImage {
+        match self {
+            Decoded::Image(image) => precise::from_rgba(&image.to_rgba()),
+            Decoded::Deep(image) => image.into_precise(),
+            Decoded::Hdr(image) => tone_mapping.apply(&image),
+        }
+    }
+}
+
+/// Whether the ISO base media file starting `data` is an AVIF image,
+/// according to the brands of its `ftyp` box.
+fn is_avif(data: &[u8]) -> bool {
+    if data.len() < 16 || &data[4..8] != b"ftyp" {
+        return false
+    }
+    let size = (data[0] as usize) << 24 | (data[1] as usize) << 16
+        | (data[2] as usize) << 8 | data[3] as usize;
+    let brands = &data[8..size.min(data.len()).max(8)];
+    // The major brand, the minor version and then the compatible brands.
+    brands.chunks(4)
+        .enumerate()
+        .filter(|&(i, _)| i != 1)
+        .any(|(_, brand)| brand == b"avif" || brand == b"avis")
+}
+
+/// Guesses the format of an image from its first bytes. TGA images have no
+/// magic bytes, so they are only recognized by their file `extension` or
+/// by the footer of version 2 files.
+pub fn detect(data: &[u8], extension: Option<&str>) -> Option<Format> {
+    let starts = |magic: &[u8]| data.starts_with(magic);
+    let format = if starts(b"\x89PNG\r\n\x1a\n") {
+        Format::Png
+    } else if starts(b"\xff\xd8\xff") {
+        Format::Jpeg
+    } else if starts(b"GIF87a") || starts(b"GIF89a") {
+        Format::Gif
+    } else if starts(b"BM") {
+        Format::Bmp
+    } else if starts(b"\x00\x00\x01\x00") {
+        Format::Ico
+    } else if starts(b"II*\x00") || starts(b"MM\x00*") {
+        Format::Tiff
+    } else if starts(b"RIFF") && data.get(8..12) == Some(&b"WEBP"[..]) {
+        Format::WebP
+    } else if is_avif(data) {
+        Format::Avif
+    } else if starts(b"qoif") {
+        Format::Qoi
+    } else if starts(b"farbfeld") {
+        Format::Farbfeld
+    } else if data.len() > 2 && data[0] == b'P' && data[1] >= b'1' && data[1] <= b'7'
+        && data[2].is_ascii_whitespace() {
+        Format::Pnm
+    } else if starts(b"#?RADIANCE") || starts(b"#?RGBE") {
+        Format::Radiance
+    } else if starts(b"\x76\x2f\x31\x01") {
+        Format::OpenExr
+    } else if svg::is_svg(data) {
+        Format::Svg
+    } else if data.ends_with(b"TRUEVISION-XFILE.\x00") {
+        Format::Tga
+    } else {
+        match extension.map(|extension| extension.to_lowercase()) {
+            Some(ref extension) if extension == "tga" => Format::Tga,
+            _ => return None,
+        }
+    };
+    Some(format)
+}
+
+fn format_error(err: String) -> ImageError {
+    ImageError::FormatError(err)
+}
+
+/// Decodes an image of a format detected with `detect`.
+pub fn decode(data: &[u8], format: Format) -> ImageResult<Decoded> {
+    let with_image = |format| image::load_from_memory_with_format(data, format)
+        .map(Decoded::Image);
+    let rgba = |image: Result<RgbaImage, String>| image
+        .map(|image| Decoded::Image(DynamicImage::ImageRgba8(image)))
+        .map_err(format_error);
+    match format {
+        // Byte 24 is the bit depth in the IHDR chunk, which comes first.
+        Format::Png if data.get(24) == Some(&16) => decode_png16(data)
+            .map(Decoded::Deep)
+            .map_err(format_error),
+        Format::Png      => with_image(ImageFormat::PNG),
+        Format::Jpeg     => with_image(ImageFormat::JPEG),
+        Format::Gif      => with_image(ImageFormat::GIF),
+        Format::Bmp      => with_image(ImageFormat::BMP),
+        Format::Ico      => with_image(ImageFormat::ICO),
+        Format::Tiff     => with_image(ImageFormat::TIFF),
+        Format::Tga      => with_image(ImageFormat::TGA),
+        Format::WebP     => rgba(decode_webp(data)),
+        Format::Avif     => rgba(decode_avif(data)),
+        Format::Qoi      => rgba(qoi::decode(data)),
+        Format::Pnm      => rgba(pnm::decode(data)),
+        Format::Farbfeld => decode_farbfeld(data).map(Decoded::Deep).map_err(format_error),
+        Format::Radiance => hdr::decode_radiance(data).map(Decoded::Hdr).map_err(format_error),
+        Format::OpenExr  => hdr::decode_exr(data).map(Decoded::Hdr).map_err(format_error),
+        Format::Svg      => Err(format_error("SVG images can only be read from a file".into())),
+    }
+}
+
+/// Decodes a WebP image, lossy or lossless, with or without alpha. Only the
+/// first frame of animations is shown.
+fn decode_webp(data: &[u8]) -> Result<RgbaImage, String> {
+    let error = |err| format!("Invalid WebP image: {:?}", err);
+    let mut decoder = WebPDecoder::new(Cursor::new(data)).map_err(error)?;
+    let (width, height) = decoder.dimensions();
+    let size = decoder.output_buffer_size().ok_or("WebP image is too large")?;
+    let mut pixels = vec![0; size];
+    decoder.read_image(&mut pixels).map_err(error)?;
+    if !decoder.has_alpha() {
+        let mut rgba = Vec::with_capacity(size / 3 * 4);
+        for rgb in pixels.chunks(3) {
+            rgba.extend_from_slice(rgb);
+            rgba.push(255);
+        }
+        pixels = rgba;
+    }
+    ImageBuffer::from_raw(width, height, pixels).ok_or_else(|| "Invalid WebP image".to_string())
+}
+
+#[cfg(feature = "avif")]
+fn decode_avif(data: &[u8]) -> Result<RgbaImage, String> {
+    let pixels = ::libavif::decode_rgb(data)
+        .map_err(|_| "Invalid AVIF image".to_string())?;
+    Ok(ImageBuffer::from_fn(pixels.width(), pixels.height(), |x, y| {
+        let (r, g, b, a) = pixels.pixel(x, y);
+        image::Rgba { data: [r, g, b, a] }
+    }))
+}
+
+#[cfg(not(feature = "avif"))]
+fn decode_avif(_data: &[u8]) -> Result<RgbaImage, String> {
+    Err("Can't decode AVIF images, wc-bg was built without the avif feature \
+         (it needs a C compiler and CMake, build with --features avif)".into())
+}
+
+/// Reads big endian 16 bit samples as floats between 0 and 1.
+fn samples_16<'a>(data: &'a [u8]) -> impl Iterator<Item = f32> + 'a {
+    data.chunks(2).map(|sample| ((sample[0] as u32) << 8 | sample[1] as u32) as f32 / 65535.0)
+}
+
+/// Decodes a PNG image with 16 bits per channel, which the `image` crate
+/// would truncate.
+fn decode_png16(data: &[u8]) -> Result<FloatImage, String> {
+    let error = |err: png::DecodingError| format!("Invalid PNG image: {}", err);
+    let mut decoder = png::Decoder::new(Cursor::new(data));
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info().map_err(error)?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).map_err(error)?;
+    if info.bit_depth != png::BitDepth::Sixteen {
+        return Err("Expected a 16 bit PNG image".into())
+    }
+    let mut image = FloatImage::new(info.width, info.height);
+    let channels = info.color_type.samples();
+    let samples: Vec<f32> = samples_16(&buffer[..info.buffer_size()]).collect();
+    for (pixel, samples) in image.pixels.iter_mut().zip(samples.chunks(channels)) {
+        *pixel = match *samples {
+            [gray] => [gray, gray, gray, 1.0],
+            [gray, alpha] => [gray, gray, gray, alpha],
+            [r, g, b] => [r, g, b, 1.0],
+            [r, g, b, a] => [r, g, b, a],
+            _ => return Err("Unsupported PNG color type".into()),
+        };
+    }
+    Ok(image)
+}
+
+/// Decodes a farbfeld image, which always has 16 bits per channel.
+fn decode_farbfeld(data: &[u8]) -> Result<FloatImage, String> {
+    let read_u32 = |at: usize| (data[at] as u32) << 24 | (data[at + 1] as u32) << 16
+        | (data[at + 2] as u32) << 8 | data[at + 3] as u32;
+    if data.len() < 16 {
+        return Err("Invalid farbfeld image".into())
+    }
+    let (width, height) = (read_u32(8), read_u32(12));
+    let samples = &data[16..];
+    if samples.len() as u64 != width as u64 * height as u64 * 8 {
+        return Err("Farbfeld image has the wrong size".into())
+    }
+    let mut image = FloatImage::new(width, height);
+    let samples: Vec<f32> = samples_16(samples).collect();
+    for (pixel, samples) in image.pixels.iter_mut().zip(samples.chunks(4)) {
+        *pixel = [samples[0], samples[1], samples[2], samples[3]];
+    }
+    Ok(image)
+}
+
+#[test]
+fn test_detect() {
+    assert_eq!(detect(b"\x89PNG\r\n\x1a\n....", None), Some(Format::Png));
+    assert_eq!(detect(b"RIFF\x00\x00\x00\x00WEBPVP8 ", None), Some(Format::WebP));
+    assert_eq!(detect(b"\x00\x00\x00\x1cftypmif1\x00\x00\x00\x00mif1avifmiaf", None),
+               Some(Format::Avif));
+    assert_eq!(detect(b"\x00\x00\x00\x18ftypisom\x00\x00\x00\x00isommp41", None), None);
+    assert_eq!(detect(b"P6\n1 1\n255\n...", None), Some(Format::Pnm));
+    assert_eq!(detect(b"\x00\x00\x02\x00", Some("TGA")), Some(Format::Tga));
+    assert_eq!(detect(b"\x00\x00\x02\x00", None), None);
+
+    let farbfeld = b"farbfeld\x00\x00\x00\x01\x00\x00\x00\x01\xff\xff\x00\x00\x00\x00\xff\xff";
+    let image = decode(farbfeld, Format::Farbfeld).unwrap()
+        .into_precise(&ToneMapping::default());
+    assert_eq!(image.into_raw(), vec![255.0, 0.0, 0.0, 255.0]);
+}
@@ -0,0 +1,29 @@
+//! Frame callbacks, telling when the compositor is ready for a new frame of
+//! a background.
+
+use wayland_client::EventQueueHandle;
+use wayland_client::protocol::wl_callback;
+
+/// Handles the frame callbacks of one background surface.
+pub struct FrameHandler {
+    /// Whether the last requested frame has been shown, so the next one
+    /// can be drawn.
+    pub done: bool,
+}
+
+impl FrameHandler {
+    pub fn new() -> FrameHandler {
+        FrameHandler { done: true }
+    }
+}
+
+impl wl_callback::Handler for FrameHandler {
+    fn done(&mut self,
+            _evqh: &mut EventQueueHandle,
+            _proxy: &wl_callback::WlCallback,
+            _callback_data: u32) {
+        self.done = true;
+    }
+}
+
+declare_handler!(FrameHandler, wl_callback::Handler, wl_callback::WlCallback);
@@ -0,0 +1,285 @@
+//! Images with more than 8 bits per channel, and tone mapping of high
+//! dynamic range images (Radiance HDR, OpenEXR) for the display.
+//!
+//! Both are kept in floats (see `precise`), so they are scaled and filtered
+//! with their full precision and dithered once with the rest of the
+//! background.
+
+use std::io::Cursor;
+use std::str::FromStr;
+
+use image::{ImageBuffer, Rgba};
+
+use precise::PreciseImage;
+
+/// An image with a float per channel and straight alpha.
+#[derive(Debug, Clone)]
+pub struct FloatImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<[f32; 4]>,
+}
+
+impl FloatImage {
+    pub fn new(width: u32, height: u32) -> FloatImage {
+        FloatImage {
+            width: width,
+            height: height,
+            pixels: vec![[0.0, 0.0, 0.0, 1.0]; width as usize * height as usize],
+        }
+    }
+
+    fn get(&self, x: u32, y: u32) -> [f32; 4] {
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    fn set(&mut self, x: u32, y: u32, pixel: [f32; 4]) {
+        self.pixels[(y * self.width + x) as usize] = pixel;
+    }
+
+    /// Converts to channels between 0 and 255, without rounding them. The
+    /// colors must already be encoded for the display, between 0 and 1.
+    pub fn into_precise(self) -> PreciseImage {
+        ImageBuffer::from_fn(self.width, self.height, |x, y| {
+            let pixel = self.get(x, y);
+            let mut data = [0.0; 4];
+            for c in 0..4 {
+                data[c] = pixel[c].max(0.0).min(1.0) * 255.0;
+            }
+            Rgba { data: data }
+        })
+    }
+}
+
+/// How the brightness of a high dynamic range image is compressed into
+/// what the display can show.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToneMap {
+    /// The filmic curve of the ACES reference rendering transform, in
+    /// Krzysztof Narkowicz's fit. Rolls off highlights per channel.
+    Aces,
+
+    /// Reinhard's operator on the luminance, keeping hues, with the
+    /// brightest pixel mapped to white.
+    Reinhard,
+
+    /// Cuts off everything brighter than white.
+    Clamp,
+}
+
+impl FromStr for ToneMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ToneMap, String> {
+        match s {
+            "aces"     => Ok(ToneMap::Aces),
+            "reinhard" => Ok(ToneMap::Reinhard),
+            "clamp"    => Ok(ToneMap::Clamp),
+            _          => Err(format!("Invalid tone mapping: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ToneMapping {
+    pub operator: ToneMap,
+    /// Exposure compensation in stops, on top of the automatic exposure.
+    pub exposure: f32,
+}
+
+impl Default for ToneMapping {
+    fn default() -> ToneMapping {
+        ToneMapping {
+            operator: ToneMap::Aces,
+            exposure: 0.0,
+        }
+    }
+}
+
+fn luminance(pixel: [f32; 4]) -> f32 {
+    0.2126 * pixel[0] + 0.7152 * pixel[1] + 0.0722 * pixel[2]
+}
+
+/// Decodes a value encoded with the sRGB transfer function to linear light.
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.040_45 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Encodes a linear value with the sRGB transfer function.
+pub fn linear_to_srgb(value: f32) -> f32 {
+    let value = value.max(0.0).min(1.0);
+    if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn aces(value: f32) -> f32 {
+    (value * (2.51 * value + 0.03)) / (value * (2.43 * value + 0.59) + 0.14)
+}
+
+impl ToneMapping {
+    /// Maps a linear high dynamic range image to sRGB.
+    ///
+    /// The exposure is chosen so that the log-average luminance of the image
+    /// lands on middle gray, like a camera's automatic exposure would.
+    pub fn apply(&self, image: &FloatImage) -> PreciseImage {
+        let count = image.pixels.len().max(1) as f64;
+        let log_average = (image.pixels.iter()
+            .map(|&pixel| (1e-4 + luminance(pixel).max(0.0) as f64).ln())
+            .sum::<f64>() / count).exp();
+        let scale = (0.18 / log_average) as f32 * self.exposure.exp2();
+        let white = image.pixels.iter()
+            .map(|&pixel| luminance(pixel) * scale)
+            .fold(1.0f32, f32::max);
+
+        let mut mapped = image.clone();
+        for pixel in &mut mapped.pixels {
+            let exposed = [pixel[0].max(0.0) * scale,
+                           pixel[1].max(0.0) * scale,
+                           pixel[2].max(0.0) * scale];
+            let display = match self.operator {
+                ToneMap::Aces => [aces(exposed[0]), aces(exposed[1]), aces(exposed[2])],
+                ToneMap::Reinhard => {
+                    let lum = luminance([exposed[0], exposed[1], exposed[2], 1.0]);
+                    let factor = if lum > 0.0 {
+                        (1.0 + lum / (white * white)) / (1.0 + lum)
+                    } else {
+                        0.0
+                    };
+                    [exposed[0] * factor, exposed[1] * factor, exposed[2] * factor]
+                },
+                ToneMap::Clamp => exposed,
+            };
+            for c in 0..3 {
+                pixel[c] = linear_to_srgb(display[c]);
+            }
+        }
+        mapped.into_precise()
+    }
+}
+
+/// Decodes a Radiance HDR (RGBE) image, flat or run length encoded.
+pub fn decode_radiance(data: &[u8]) -> Result<FloatImage, String> {
+    let invalid = |what: &str| format!("Invalid Radiance HDR image: {}", what);
+    // The header is text lines up to an empty one, then the resolution.
+    let mut position = 0;
+    let mut read_line = || -> Result<String, String> {
+        let end = data[position..].iter().position(|&b| b == b'\n')
+            .ok_or_else(|| invalid("header"))?;
+        let line = String::from_utf8_lossy(&data[position..position + end]).trim().to_string();
+        position += end + 1;
+        Ok(line)
+    };
+    if !read_line()?.starts_with("#?") {
+        return Err(invalid("missing #? signature"))
+    }
+    loop {
+        let line = read_line()?;
+        if line.is_empty() {
+            break
+        }
+        if line.starts_with("FORMAT=") && line != "FORMAT=32-bit_rle_rgbe" {
+            return Err(invalid("only RGBE is supported, not XYZE"))
+        }
+    }
+    let resolution = read_line()?;
+    let resolution: Vec<&str> = resolution.split_whitespace().collect();
+    let (flip, height, width) = match resolution[..] {
+        ["-Y", height, "+X", width] => (false, height, width),
+        ["+Y", height, "+X", width] => (true, height, width),
+        _ => return Err(invalid("unsupported orientation")),
+    };
+    let height = height.parse::<u32>().map_err(|_| invalid("height"))?;
+    let width = width.parse::<u32>().map_err(|_| invalid("width"))?;
+    if width == 0 || height == 0 || width as usize * height as usize / 128 > data.len() {
+        return Err(invalid("size"))
+    }
+
+    let mut bytes = data[position..].iter().cloned();
+    let mut next = || bytes.next().ok_or_else(|| invalid("truncated"));
+    let mut image = FloatImage::new(width, height);
+    let mut scanline = vec![[0u8; 4]; width as usize];
+    for row in 0..height {
+        let first = [next()?, next()?, next()?, next()?];
+        let run_length_encoded = width >= 8 && width < 0x8000 && first[0] == 2 && first[1] == 2
+            && ((first[2] as u32) << 8 | first[3] as u32) == width;
+        if run_length_encoded {
+            // Every channel of the scanline is encoded on its own.
+            for c in 0..4 {
+                let mut x = 0;
+                while x < width as usize {
+                    let count = next()? as usize;
+                    let (count, run) = if count > 128 { (count - 128, true) } else { (count, false) };
+                    if count == 0 || x + count > width as usize {
+                        return Err(invalid("run length"))
+                    }
+                    let value = if run { next()? } else { 0 };
+                    for pixel in &mut scanline[x..x + count] {
+                        pixel[c] = if run { value } else { next()? };
+                    }
+                    x += count;
+                }
+            }
+        } else {
+            scanline[0] = first;
+            for pixel in &mut scanline[1..] {
+                *pixel = [next()?, next()?, next()?, next()?];
+            }
+        }
+        let y = if flip { height - 1 - row } else { row };
+        for (x, rgbe) in scanline.iter().enumerate() {
+            let pixel = if rgbe[3] == 0 {
+                [0.0, 0.0, 0.0, 1.0]
+            } else {
+                let factor = 2f32.powi(rgbe[3] as i32 - 136);
+                [(rgbe[0] as f32 + 0.5) * factor,
+                 (rgbe[1] as f32 + 0.5) * factor,
+                 (rgbe[2] as f32 + 0.5) * factor,
+                 1.0]
+            };
+            image.set(x as u32, y, pixel);
+        }
+    }
+    Ok(image)
+}
+
+/// Decodes the first RGBA layer of an OpenEXR image at its largest
+/// resolution.
+pub fn decode_exr(data: &[u8]) -> Result<FloatImage, String> {
+    use exr::prelude::*;
+
+    let image = read()
+        .no_deep_data()
+        .largest_resolution_level()
+        .rgba_channels(
+            |resolution, _| FloatImage::new(resolution.width() as u32, resolution.height() as u32),
+            |image: &mut FloatImage, position, (r, g, b, a): (f32, f32, f32, f32)| {
+                image.set(position.x() as u32, position.y() as u32, [r, g, b, a]);
+            })
+        .first_valid_layer()
+        .all_attributes()
+        .from_buffered(Cursor::new(data))
+        .map_err(|err| format!("Invalid OpenEXR image: {}", err))?;
+    Ok(image.layer_data.channel_data.pixels)
+}
+
+#[test]
+fn test_radiance() {
+    let mut data = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 8\n".to_vec();
+    // Red with a run of 8, green and blue as literals, the exponent as a run.
+    data.extend_from_slice(&[2, 2, 0, 8, 136, 128, 8, 0, 0, 0, 0, 0, 0, 0, 0, 136, 0, 136, 129]);
+    let image = decode_radiance(&data).unwrap();
+    assert_eq!((image.width, image.height), (8, 1));
+    assert_eq!(image.get(3, 0), [1.0039062, 0.00390625, 0.00390625, 1.0]);
+
+    let bright = FloatImage { width: 1, height: 1, pixels: vec![[100.0, 100.0, 100.0, 1.0]] };
+    let mapped = ToneMapping::default().apply(&bright);
+    // Automatic exposure brings a flat image to middle gray.
+    assert!(mapped.get_pixel(0, 0)[0] > 100.0 && mapped.get_pixel(0, 0)[0] < 160.0);
+}
@@ -0,0 +1,264 @@
+//! Color management with ICC profiles.
+//!
+//! Images tagged with another profile than sRGB, e.g. Adobe RGB or Display
+//! P3, are converted to sRGB when they are decoded, so they don't look
+//! washed out. Rendered backgrounds can then be converted from sRGB to the
+//! profile of the output they are shown on, from `--output-profile`, before
+//! they are dithered to 8 bits.
+
+use std::io::Cursor;
+use std::rc::Rc;
+
+use image::{ImageBuffer, Rgba, RgbaImage};
+use image_webp::WebPDecoder;
+use png;
+use qcms::{DataType, Intent, Profile, Transform};
+
+use way_cooler_client_helpers::wayland::Resolution;
+
+use exif;
+use format::Format;
+use load;
+use precise::PreciseImage;
+use transform::{self, Selector};
+
+/// The ICC profile embedded in a JPEG, PNG or WebP image, if it has one.
+pub fn embedded_profile(data: &[u8], format: Format) -> Option<Vec<u8>> {
+    match format {
+        Format::Jpeg => jpeg_profile(data),
+        Format::Png => {
+            let reader = png::Decoder::new(Cursor::new(data)).read_info().ok()?;
+            let profile = reader.info().icc_profile.as_ref().map(|profile| profile.to_vec());
+            profile
+        },
+        Format::WebP => WebPDecoder::new(Cursor::new(data)).ok()?.icc_profile().ok()?,
+        _ => None,
+    }
+}
+
+/// Reads the profile from the APP2 segments of a JPEG. Profiles that don't
+/// fit in one segment are split over several, numbered from 1.
+fn jpeg_profile(data: &[u8]) -> Option<Vec<u8>> {
+    const SIGNATURE: &'static [u8] = b"ICC_PROFILE\0";
+    let mut chunks: Vec<(u8, &[u8])> = exif::jpeg_segments(data, 0xE2).into_iter()
+        .filter(|segment| segment.starts_with(SIGNATURE) && segment.len() > SIGNATURE.len() + 2)
+        .map(|segment| (segment[SIGNATURE.len()], &segment[SIGNATURE.len() + 2..]))
+        .collect();
+    if chunks.is_empty() {
+        return None
+    }
+    chunks.sort_by_key(|&(number, _)| number);
+    Some(chunks.into_iter().flat_map(|(_, chunk)| chunk.iter().cloned()).collect())
+}
+
+/// Converts an image from one profile to another. Profiles qcms can't
+/// convert between, e.g. grayscale ones, leave the image as it is.
+fn convert(image: RgbaImage, from: &Profile, to: &Profile) -> RgbaImage {
+    let transform = match Transform::new(from, to, DataType::RGBA8, Intent::default()) {
+        Some(transform) => transform,
+        None => return image,
+    };
+    let (width, height) = image.dimensions();
+    let mut pixels = image.into_vec();
+    transform.apply(&mut pixels);
+    ImageBuffer::from_raw(width, height, pixels).expect("Converted image has the wrong size")
+}
+
+/// Converts an image tagged with the ICC `profile` to sRGB. Invalid
+/// profiles are ignored.
+pub fn to_srgb(image: RgbaImage, profile: &[u8]) -> RgbaImage {
+    match Profile::new_from_slice(profile, false) {
+        Some(ref profile) if !profile.is_sRGB() => convert(image, profile, &Profile::new_sRGB()),
+        _ => image,
+    }
+}
+
+/// Like `to_srgb`, for images with more than 8 bits per channel, whose
+/// colors are converted through a `Conversion` so they stay between the
+/// 8 bit steps.
+pub fn to_srgb_precise(mut image: PreciseImage, profile: &[u8]) -> PreciseImage {
+    let conversion = match Profile::new_from_slice(profile, false) {
+        Some(ref profile) if !profile.is_sRGB() => Conversion::between(profile, &Profile::new_sRGB()),
+        _ => None,
+    };
+    if let Some(conversion) = conversion {
+        for pixel in image.pixels_mut() {
+            conversion.convert(pixel);
+        }
+    }
+    image
+}
+
+/// Nodes of a `Conversion`'s lookup table per channel, every fifth 8 bit
+/// value.
+const LUT_NODES: usize = 52;
+
+/// A conversion between two profiles. qcms only converts 8 bit values, so
+/// it is sampled into a lookup table once, which converts colors between
+/// the 8 bit steps too.
+#[derive(Debug)]
+pub struct Conversion {
+    table: Vec<[f32; 3]>,
+}
+
+impl Conversion {
+    /// The conversion from sRGB to `profile`, if qcms can convert to it.
+    pub fn to_profile(profile: &Profile) -> Option<Conversion> {
+        Conversion::between(&Profile::new_sRGB(), profile)
+    }
+
+    /// The conversion from `from` to `to`, if qcms can convert between them.
+    pub fn between(from: &Profile, to: &Profile) -> Option<Conversion> {
+        let transform = Transform::new(from, to, DataType::RGB8, Intent::default())?;
+        let step = 255 / (LUT_NODES - 1);
+        let mut nodes = Vec::with_capacity(LUT_NODES * LUT_NODES * LUT_NODES * 3);
+        for r in 0..LUT_NODES {
+            for g in 0..LUT_NODES {
+                for b in 0..LUT_NODES {
+                    nodes.extend_from_slice(&[(r * step) as u8, (g * step) as u8, (b * step) as u8]);
+                }
+            }
+        }
+        transform.apply(&mut nodes);
+        let table = nodes.chunks(3)
+            .map(|node| [node[0] as f32, node[1] as f32, node[2] as f32])
+            .collect();
+        Some(Conversion { table: table })
+    }
+
+    /// Converts the color of a pixel with channels between 0 and 255,
+    /// interpolating between the nodes around it.
+    pub fn convert(&self, pixel: &mut Rgba<f32>) {
+        let step = 255.0 / (LUT_NODES - 1) as f32;
+        let mut below = [0; 3];
+        let mut fraction = [0.0; 3];
+        for c in 0..3 {
+            let position = (pixel[c] / step).max(0.0).min((LUT_NODES - 1) as f32);
+            below[c] = (position as usize).min(LUT_NODES - 2);
+            fraction[c] = position - below[c] as f32;
+        }
+        let mut color = [0.0; 3];
+        for corner in 0..8 {
+            let mut index = 0;
+            let mut weight = 1.0;
+            for c in 0..3 {
+                let above = (corner >> (2 - c)) & 1;
+                index = index * LUT_NODES + below[c] + above;
+                weight *= if above == 1 { fraction[c] } else { 1.0 - fraction[c] };
+            }
+            let node = &self.table[index];
+            for c in 0..3 {
+                color[c] += weight * node[c];
+            }
+        }
+        pixel.data[..3].copy_from_slice(&color);
+    }
+}
+
+/// The `--output-profile` rules, resolved per output.
+#[derive(Debug, Clone, Default)]
+pub struct OutputProfiles {
+    rules: Vec<(Selector, Rc<Conversion>)>,
+}
+
+impl OutputProfiles {
+    /// Adds an `--output-profile` value, e.g. `p3.icc` or `1=p3.icc`.
+    pub fn add(&mut self, s: &str) -> Result<(), String> {
+        let (selector, path) = transform::split_selector(s)?;
+        let profile = load::read(path).map_err(|err| format!("Could not read {}: {}", path, err))?;
+        let profile = Profile::new_from_slice(&profile, false)
+            .ok_or_else(|| format!("Invalid ICC profile: {}", path))?;
+        let conversion = Conversion::to_profile(&profile)
+            .ok_or_else(|| format!("Can't convert to ICC profile: {}", path))?;
+        self.rules.push((selector, Rc::new(conversion)));
+        Ok(())
+    }
+
+    /// The conversion to the profile of the `index`-th output, if it has
+    /// one. The last rule matching the output wins.
+    pub fn for_output(&self, index: usize, resolution: Resolution) -> Option<Rc<Conversion>> {
+        self.rules.iter().rev()
+            .find(|&&(selector, _)| selector.matches(index, resolution))
+            .map(|&(_, ref conversion)| conversion.clone())
+    }
+
+    /// Converts a background rendered in sRGB to the profile of the
+    /// `index`-th output.
+    pub fn apply(&self, mut image: PreciseImage, index: usize, resolution: Resolution) -> PreciseImage {
+        if let Some(conversion) = self.for_output(index, resolution) {
+            for pixel in image.pixels_mut() {
+                conversion.convert(pixel);
+            }
+        }
+        image
+    }
+}
+
+#[test]
+fn test_jpeg_profile() {
+    let mut data = vec![0xFF, 0xD8];
+    // The second chunk comes first in the file.
+    for &(number, chunk) in &[(2u8, &b"world"[..]), (1, &b"hello "[..])] {
+        let length = 2 + 12 + 2 + chunk.len();
+        data.extend_from_slice(&[0xFF, 0xE2, (length >> 8) as u8, length as u8]);
+        data.extend_from_slice(b"ICC_PROFILE\0");
+        data.extend_from_slice(&[number, 2]);
+        data.extend_from_slice(chunk);
+    }
+    data.extend_from_slice(&[0xFF, 0xDA]);
+    assert_eq!(embedded_profile(&data, Format::Jpeg), Some(b"hello world".to_vec()));
+    assert_eq!(embedded_profile(&data[..4], Format::Jpeg), None);
+}
+
+#[cfg(test)]
+fn display_p3() -> Box<Profile> {
+    use qcms::{CIE_xyY, CIE_xyYTRIPLE};
+
+    let xy = |x, y| CIE_xyY { x: x, y: y, Y: 1.0 };
+    let primaries = CIE_xyYTRIPLE { red: xy(0.680, 0.320), green: xy(0.265, 0.690), blue: xy(0.150, 0.060) };
+    Profile::new_rgb_with_gamma_set(xy(0.3127, 0.3290), primaries, 2.2, 2.2, 2.2).unwrap()
+}
+
+#[test]
+fn test_conversion() {
+    let conversion = Conversion::to_profile(&display_p3()).unwrap();
+    let convert = |color: [f32; 3]| {
+        let mut pixel = Rgba { data: [color[0], color[1], color[2], 255.0] };
+        conversion.convert(&mut pixel);
+        pixel.data
+    };
+    // sRGB red lies inside the wider P3 gamut. The test profile has a plain
+    // 2.2 gamma, real Display P3 profiles give about 234, 51, 35.
+    let red = convert([255.0, 0.0, 0.0]);
+    for (&c, &expected) in red.iter().zip(&[233.0, 54.0, 40.0, 255.0]) {
+        assert!((c - expected).abs() <= 2.0, "{:?}", red);
+    }
+    // Grays stay gray, also between the nodes of the table.
+    let gray = convert([127.5, 127.5, 127.5]);
+    assert!((gray[0] - gray[1]).abs() < 1.0 && (gray[1] - gray[2]).abs() < 1.0, "{:?}", gray);
+    assert!((gray[0] - 127.5).abs() <= 3.0, "{:?}", gray);
+    // The same conversion back with qcms restores the color.
+    let back = Transform::new(&display_p3(), &Profile::new_sRGB(), DataType::RGB8, Intent::default())
+        .unwrap();
+    let mut pixel = [red[0].round() as u8, red[1].round() as u8, red[2].round() as u8];
+    back.apply(&mut pixel);
+    assert!(pixel[0] >= 252 && pixel[1] <= 3 && pixel[2] <= 3, "{:?}", pixel);
+}
+
+#[test]
+fn test_output_selectors() {
+    use image::ImageBuffer;
+
+    let (selector, _) = transform::split_selector("1=p3.icc").unwrap();
+    let profiles = OutputProfiles {
+        rules: vec![(selector, Rc::new(Conversion::to_profile(&display_p3()).unwrap()))],
+    };
+    let resolution = Resolution { w: 1920, h: 1080 };
+    let red = ImageBuffer::from_pixel(2, 2, Rgba { data: [255.0, 0.0, 0.0, 255.0] });
+    // Only the second output has the profile.
+    let first = profiles.apply(red.clone(), 0, resolution);
+    assert_eq!(first.get_pixel(0, 0).data, [255.0, 0.0, 0.0, 255.0]);
+    let second = profiles.apply(red, 1, resolution);
+    assert!(second.get_pixel(0, 0)[0] < 240.0 && second.get_pixel(0, 0)[1] > 40.0);
+    assert_eq!(second.get_pixel(0, 0)[3], 255.0);
+}
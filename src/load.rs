@@ -0,0 +1,143 @@
+//! Reading and decoding of the background image.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::str::FromStr;
+
+use image::{ImageBuffer, ImageError, ImageResult, RgbaImage};
+
+use exif;
+use format::{self, Decoded, Format};
+use hdr::ToneMapping;
+use icc;
+use precise::{self, PreciseImage};
+
+/// Reads the image file at `path`, or standard input if it is `-`.
+pub fn read(path: &str) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    if path == "-" {
+        io::stdin().read_to_end(&mut data)?;
+    } else {
+        File::open(path)?.read_to_end(&mut data)?;
+    }
+    Ok(data)
+}
+
+/// Decodes an encoded image read from `path`, turning it upright according
+/// to its EXIF orientation and converting it to sRGB according to its ICC
+/// profile, so that every later step sees the image as it is meant to be
+/// displayed.
+///
+/// The format is told by the magic bytes of the data, the path's extension
+/// is only needed for formats without any. Images with more than 8 bits per
+/// channel keep their precision, high dynamic range ones are tone mapped
+/// with `tone_mapping`.
+pub fn decode(data: &[u8], path: &str, tone_mapping: &ToneMapping) -> ImageResult<PreciseImage> {
+    let extension = Path::new(path).extension().and_then(|extension| extension.to_str());
+    let format = format::detect(data, extension)
+        .ok_or_else(|| ImageError::FormatError(
+            "Unsupported image format, expected PNG, JPEG, GIF, BMP, ICO, TIFF, WebP, \
+             AVIF, QOI, PNM, TGA, farbfeld, Radiance HDR, OpenEXR or SVG".into()))?;
+    let image = match (format::decode(data, format)?, icc::embedded_profile(data, format)) {
+        (Decoded::Image(image), Some(profile)) =>
+            precise::from_rgba(&icc::to_srgb(image.to_rgba(), &profile)),
+        (decoded, Some(profile)) =>
+            icc::to_srgb_precise(decoded.into_precise(tone_mapping), &profile),
+        (decoded, None) => decoded.into_precise(tone_mapping),
+    };
+    Ok(match format {
+        Format::Jpeg => match exif::jpeg_orientation(data) {
+            Some(orientation) => orientation.apply(image),
+            None => image
+        },
+        _ => image
+    })
+}
+
+/// The byte order of the pixels of raw image data, with straight alpha.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RawFormat {
+    Rgba,
+    Argb,
+    /// What ARGB words are in memory on little endian machines, e.g.
+    /// Wayland's and Cairo's ARGB32.
+    Bgra,
+}
+
+impl FromStr for RawFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<RawFormat, String> {
+        match s {
+            "rgba" => Ok(RawFormat::Rgba),
+            "argb" => Ok(RawFormat::Argb),
+            "bgra" => Ok(RawFormat::Bgra),
+            _      => Err(format!("Invalid raw format: {}", s)),
+        }
+    }
+}
+
+/// The layout of raw pixel data given with `--raw`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawImage {
+    pub width: u32,
+    pub height: u32,
+    pub format: RawFormat,
+}
+
+impl FromStr for RawImage {
+    type Err = String;
+
+    /// Parses `WIDTHxHEIGHT[:FORMAT]`, the format defaults to `rgba`.
+    fn from_str(s: &str) -> Result<RawImage, String> {
+        let invalid = || format!("Invalid raw image, expected WIDTHxHEIGHT[:FORMAT]: {}", s);
+        let mut parts = s.splitn(2, ':');
+        let size = parts.next().ok_or_else(invalid)?;
+        let format = match parts.next() {
+            Some(format) => format.parse()?,
+            None => RawFormat::Rgba
+        };
+        let mut dimensions = size.splitn(2, 'x').map(|n| n.parse::<u32>().ok());
+        match (dimensions.next(), dimensions.next()) {
+            (Some(Some(width)), Some(Some(height))) if width > 0 && height > 0 =>
+                Ok(RawImage { width: width, height: height, format: format }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl RawImage {
+    /// Turns raw pixel data into an image, the data has to be exactly
+    /// four bytes for every pixel.
+    pub fn decode(&self, data: &[u8]) -> ImageResult<PreciseImage> {
+        let expected = self.width as usize * self.height as usize * 4;
+        if data.len() != expected {
+            return Err(ImageError::FormatError(
+                format!("A raw {}x{} image needs {} bytes, got {}",
+                        self.width, self.height, expected, data.len())))
+        }
+        let mut pixels = data.to_vec();
+        for pixel in pixels.chunks_mut(4) {
+            match self.format {
+                RawFormat::Rgba => {},
+                RawFormat::Argb => pixel.rotate_left(1),
+                RawFormat::Bgra => pixel.swap(0, 2),
+            }
+        }
+        ImageBuffer::from_raw(self.width, self.height, pixels)
+            .map(|image: RgbaImage| precise::from_rgba(&image))
+            .ok_or_else(|| ImageError::FormatError("Invalid raw image".into()))
+    }
+}
+
+#[test]
+fn test_raw_formats() {
+    let rgba = [1, 2, 3, 4];
+    for &(format, data) in &[("rgba", [1, 2, 3, 4]), ("argb", [4, 1, 2, 3]), ("bgra", [3, 2, 1, 4])] {
+        let raw = format!("1x1:{}", format).parse::<RawImage>().unwrap();
+        let image = raw.decode(&data).unwrap().into_raw();
+        assert_eq!(image, vec![1.0, 2.0, 3.0, 4.0], "{}", format);
+    }
+    assert!("2x1".parse::<RawImage>().unwrap().decode(&rgba).is_err());
+}
@@ -1,37 +1,49 @@
+#![cfg_attr(feature = "unstable", feature(test))]
+
 #[macro_use] extern crate wayland_client;
 #[macro_use] extern crate wayland_sys;
 
 extern crate tempfile;
 
-extern crate byteorder;
 extern crate image;
 extern crate clap;
+extern crate chrono;
+extern crate libc;
+extern crate resvg;
+extern crate image_webp;
+extern crate png;
+extern crate exr;
+extern crate qcms;
+#[cfg(feature = "avif")]
+extern crate libavif;
 #[macro_use] extern crate way_cooler_client_helpers;
 
-use way_cooler_client_helpers::color::Color;
 use way_cooler_client_helpers::wayland::Resolution;
 
-use std::mem::transmute;
+use std::env;
+use std::fmt::Display;
+use std::str::FromStr;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 use std::os::unix::io::AsRawFd;
 use std::io::Write;
-use std::str::FromStr;
-use std::cmp::{min, max};
 
 use wayland_client::EnvHandler;
 use wayland_client::protocol::{wl_compositor, wl_shell, wl_shell_surface,
                                wl_shm, wl_surface, wl_seat, wl_buffer,
-                               wl_output};
+                               wl_output, wl_callback};
 use wl_shell_surface::FullscreenMethod;
 use wl_output::WlOutput;
 use wl_shell::WlShell;
+use wl_shm::WlShm;
 use wl_seat::WlSeat;
 use wl_compositor::WlCompositor;
 
 use wl_shm::Format as WlShmFormat;
 
-use byteorder::{NativeEndian, WriteBytesExt};
-use clap::{Arg, App};
-use image::{GenericImage, DynamicImage, Pixel, FilterType, load_from_memory, open};
+use clap::{Arg, App, ArgMatches, SubCommand};
+use image::{Rgba, RgbaImage, load_from_memory};
+use chrono::{DateTime, Local};
 
 wayland_env!(WaylandEnv,
              compositor: wl_compositor::WlCompositor,
@@ -66,86 +78,637 @@ mod generated {
 
 use generated::client::desktop_shell::DesktopShell;
 
-const CURSOR: &'static [u8; 656] = include_bytes!("../assets/arrow.png");
-
-type BufferResult = Result<wl_buffer::WlBuffer, ()>;
+mod backdrop;
+mod color;
+mod config;
+mod convert;
+mod dispatch;
+mod dither;
+mod exif;
+mod filter;
+mod format;
+mod hdr;
+mod icc;
+mod frame;
+mod load;
+mod mode;
+mod overlay;
+mod palette;
+mod pattern;
+mod pnm;
+mod pool;
+mod precise;
+mod qoi;
+mod quantize;
+mod reload;
+mod render;
+mod resample;
+mod schedule;
+mod shm;
+mod sun;
+mod svg;
+mod tile;
+mod transform;
+mod transition;
+mod watermark;
 
-#[derive(Debug, Clone, Copy)]
-pub enum BackgroundMode {
-    /// Scale image to make the shortest dimension (i.e. height or width)
-    /// fit it's container pertaining aspect ratio.
-    Fill,
+use backdrop::{Backdrop, Gradient};
+use filter::Filter;
+use dither::Dither;
+use hdr::ToneMapping;
+use icc::OutputProfiles;
+use load::RawImage;
+use frame::FrameHandler;
+use mode::{BackgroundMode, ModeOptions};
+use overlay::{Template, TextOverlay};
+use pattern::Pattern;
+use pool::{BufferPool, Layout};
+use precise::PreciseImage;
+use quantize::{Method, Output};
+use reload::Reload;
+use render::{Content, Finished, RenderOptions};
+use schedule::Schedule;
+use shm::{BufferFormat, ShmFormats};
+use transform::OutputTransforms;
+use transition::Transition;
+use watermark::OutputWatermarks;
 
-    /// Scale image width to fit container width pertaining aspect ratio.
-    Fit,
-
-    /// Scale height and width to fit container's. May create distortion.
-    Stretch,
-
-    /// Do not scale image and place to center.
-    Center,
+const CURSOR: &'static [u8; 656] = include_bytes!("../assets/arrow.png");
 
-    /// Do not scale image and create repeated image forming tile-pattern.
-    Tile,
-}
+type BufferResult = Result<wl_buffer::WlBuffer, ()>;
 
-impl FromStr for BackgroundMode {
-    type Err = String;
-
-    fn from_str(s: &str) -> Result<BackgroundMode, String> {
-        match s {
-            "fill"    => Ok(BackgroundMode::Fill),
-            "fit"     => Ok(BackgroundMode::Fit),
-            "stretch" => Ok(BackgroundMode::Stretch),
-            "center"  => Ok(BackgroundMode::Center),
-            "tile"    => Ok(BackgroundMode::Tile),
-            _         => Err(String::from_str(s).unwrap()),
-        }
-    }
-}
+/// The options that have a short form, all of which take a value, used to
+/// tell whether an option from the config file was also given on the
+/// command line.
+const SHORT_OPTIONS: &'static [(&'static str, &'static str)] = &[
+    ("c", "color"),
+    ("f", "image"),
+    ("m", "mode"),
+    ("g", "gradient"),
+];
 
-fn main() {
-    let matches = App::new("WayCooler Background Service")
+fn app() -> App<'static, 'static> {
+    App::new("WayCooler Background Service")
         .version("0.1.0")
         .author("Timidger <APragmaticPlace@gmail.com>")
         .about("Service which manage and provide background to WayCooler window manager.")
+        .arg(Arg::with_name("config")
+            .long("config")
+            .value_name("FILE")
+            .help("Read options from a file, one 'option = value' per line. \
+                   Options on the command line replace those from the file"))
         .arg(Arg::with_name("color")
             .short("c")
             .long("color")
-            .value_name("HEX")
-            .help("Six digit hexa RGB code to render color background e.g. 'ffffff'
-                  NOTE: No preceding '#' or '0x'"))
+            .value_name("COLOR")
+            .help("Color of the background, e.g. 'ffffff', '#1a1a2e', '#1a1a2e80', \
+                   'rgb(26, 26, 46)', 'hsl(240, 28%, 14%)', 'steelblue', or an entry \
+                   of the theme's palette: 'wal:color4' (pywal) or 'xrdb:background' \
+                   (X resources). Palettes are read again on SIGUSR1, which reloads \
+                   all options"))
         .arg(Arg::with_name("image")
             .short("f")
             .long("image")
             .value_name("FILE")
-            .help("Path to background image (PNG, JPG, BMP, GIF)"))
+            .help("Path to background image (PNG, JPEG, GIF, BMP, ICO, TIFF, WebP, AVIF, \
+                   QOI, PNM, TGA, farbfeld, Radiance HDR, OpenEXR or SVG), or '-' to read \
+                   it from standard input"))
+        .arg(Arg::with_name("tone-map")
+            .long("tone-map")
+            .value_name("OPERATOR")
+            .help("How high dynamic range images (Radiance HDR, OpenEXR) are fit to \
+                   the display: aces (default), reinhard or clamp")
+            .requires("image"))
+        .arg(Arg::with_name("exposure")
+            .long("exposure")
+            .value_name("STOPS")
+            .help("Brighten or darken high dynamic range images, relative to the \
+                   automatic exposure, e.g. -1.5")
+            .allow_hyphen_values(true)
+            .requires("image"))
+        .arg(Arg::with_name("raw")
+            .long("raw")
+            .value_name("WIDTHxHEIGHT[:FORMAT]")
+            .help("Read the image as raw pixels with straight alpha instead of an \
+                   encoded image. FORMAT is the byte order: rgba (default), argb or \
+                   bgra (ARGB32 on little endian machines)")
+            .requires("image"))
         .arg(Arg::with_name("mode")
             .short("m")
             .long("mode")
             .value_name("BG_MODE")
             .help("Mode affecting image render on screen (fill, fit, stretch, tile)")
             .requires("image"))
-        .get_matches();
-    let default_color = u32::from_str_radix("333333", 16).unwrap().into();
-    let (color, mut image) = match (matches.value_of("color"), matches.value_of("image")) {
-        (None, None) => (default_color, Some(("".into(), BackgroundMode::Fill))),
-        (_, Some(image)) => {
-            let mode = matches.value_of("mode")
-                .map(|mode| mode.parse::<BackgroundMode>().expect("Invalid background mode"))
-                .unwrap_or(BackgroundMode::Fill);
-            (default_color, Some((image.to_string(), mode)))
+        .arg(Arg::with_name("focus")
+            .long("focus")
+            .value_name("X,Y")
+            .help("Point of the image the fill mode keeps in view when cropping, \
+                   relative to its size. Defaults to the center, 0.5,0.5")
+            .requires("image"))
+        .arg(Arg::with_name("zoom")
+            .long("zoom")
+            .value_name("FACTOR")
+            .help("Zoom further into the image in the fill mode, e.g. 1.5")
+            .requires("image"))
+        .arg(Arg::with_name("tile-scale")
+            .long("tile-scale")
+            .value_name("FACTOR")
+            .help("Scale the image before tiling it in the tile mode")
+            .requires("image"))
+        .arg(Arg::with_name("tile-offset")
+            .long("tile-offset")
+            .value_name("X,Y|center[:X,Y]")
+            .help("Where the tiles start in the tile mode: pixels from the top left \
+                   corner, or relative to a tile centered on the output")
+            .requires("image"))
+        .arg(Arg::with_name("tile-repeat")
+            .long("tile-repeat")
+            .value_name("REPEAT")
+            .help("How tiles repeat in the tile mode: repeat (default), mirror-x, \
+                   mirror-y or mirror")
+            .requires("image"))
+        .arg(Arg::with_name("padding")
+            .long("padding")
+            .value_name("color|dominant|blur[:RADIUS[:DIM]]")
+            .help("What fills the area around the image in the fit and center modes: \
+                   the background color (default), the most common color of the image, \
+                   or a blurred and darkened copy of the image. RADIUS is relative to \
+                   the output size (default 0.02), DIM is between 0 and 1 (default 0.4)")
+            .requires("image"))
+        .arg(Arg::with_name("rotate")
+            .long("rotate")
+            .value_name("[OUTPUT=]DEGREES")
+            .help("Rotate the image clockwise by 90, 180 or 270 degrees. \
+                   OUTPUT limits this to one output: its index, 'portrait' or 'landscape'")
+            .multiple(true)
+            .number_of_values(1)
+            .requires("image"))
+        .arg(Arg::with_name("flip")
+            .long("flip")
+            .value_name("[OUTPUT=]h|v|hv")
+            .help("Flip the image horizontally and/or vertically, after rotating it. \
+                   OUTPUT limits this to one output: its index, 'portrait' or 'landscape'")
+            .multiple(true)
+            .number_of_values(1)
+            .requires("image"))
+        .arg(Arg::with_name("gradient")
+            .short("g")
+            .long("gradient")
+            .value_name("COLOR,COLOR...")
+            .help("Comma separated colors of a linear gradient to render instead of \
+                   a solid color, e.g. '000000,333333' or 'wal:color0,wal:color4'"))
+        .arg(Arg::with_name("gradient-angle")
+            .long("gradient-angle")
+            .value_name("DEGREES")
+            .help("Direction of the gradient, clockwise. 0 runs top to bottom (default), \
+                   90 runs left to right")
+            .requires("gradient"))
+        .arg(Arg::with_name("pattern")
+            .long("pattern")
+            .value_name("KIND[:COLOR,COLOR...]")
+            .help("Render a pattern instead of a solid color: noise, plasma, checkerboard, \
+                   stripes or dots, e.g. 'stripes:#222,#2a2a2a'. Noise and plasma blend \
+                   the colors, the others take turns between them")
+            .conflicts_with("gradient"))
+        .arg(Arg::with_name("pattern-size")
+            .long("pattern-size")
+            .value_name("PIXELS")
+            .help("Size of the squares, stripes and space between dots, or of the \
+                   features of noise and plasma. Defaults to 64")
+            .requires("pattern"))
+        .arg(Arg::with_name("pattern-angle")
+            .long("pattern-angle")
+            .value_name("DEGREES")
+            .help("Rotate the pattern clockwise")
+            .requires("pattern"))
+        .arg(Arg::with_name("seed")
+            .long("seed")
+            .value_name("NUMBER")
+            .help("Vary noise and plasma, the same seed always renders the same pattern")
+            .requires("pattern"))
+        .arg(Arg::with_name("dither")
+            .long("dither")
+            .value_name("METHOD")
+            .help("Dither gradients, patterns and scaled images to hide banding on 8 bit \
+                   displays: none (default), ordered or blue-noise"))
+        .arg(Arg::with_name("linear-scaling")
+            .long("linear-scaling")
+            .help("Scale images in linear light instead of sRGB, which keeps fine \
+                   high-contrast detail from darkening when it is scaled down")
+            .requires("image"))
+        .arg(Arg::with_name("output-profile")
+            .long("output-profile")
+            .value_name("[OUTPUT=]FILE")
+            .help("ICC profile of the display, backgrounds are converted from sRGB to it. \
+                   OUTPUT limits this to one output: its index, 'portrait' or 'landscape'")
+            .multiple(true)
+            .number_of_values(1))
+        .arg(Arg::with_name("buffer-format")
+            .long("buffer-format")
+            .value_name("FORMAT")
+            .help("Pixel format of the buffers given to the compositor, if it supports \
+                   it: auto (default, 8 bits per channel), deep (10 bits per channel for \
+                   opaque backgrounds) or low-memory (16 bits per pixel, rounded as set by \
+                   --dither)"))
+        .arg(Arg::with_name("transparent")
+            .long("transparent")
+            .help("Keep the transparency of the image instead of drawing it over the \
+                   background color. Only useful if the compositor shows what is \
+                   behind the background")
+            .requires("image"))
+        .arg(Arg::with_name("schedule")
+            .long("schedule")
+            .value_name("FILE")
+            .help("Change the background at times of the day, or at sunrise and sunset, \
+                   as listed in FILE. Each line is 'TIME WALLPAPER [fade=SECONDS]', where \
+                   fade sets the duration of any --transition"))
+        .arg(Arg::with_name("transition")
+            .long("transition")
+            .value_name("KIND")
+            .help("How the background changes: fade (default), or wipe-DIRECTION or \
+                   slide-DIRECTION, where DIRECTION is left, right, up or down"))
+        .arg(Arg::with_name("transition-duration")
+            .long("transition-duration")
+            .value_name("SECONDS")
+            .help("How long changing the background takes. Defaults to 0, changing \
+                   it at once"))
+        .arg(Arg::with_name("transition-easing")
+            .long("transition-easing")
+            .value_name("EASING")
+            .help("How the transition speeds up and slows down: linear, ease-in, \
+                   ease-out or ease-in-out (default)"))
+        .arg(Arg::with_name("filter")
+            .long("filter")
+            .value_name("NAME[:ARGS]")
+            .help("Post-process the image, filters run in the order they are given: \
+                   blur:SIGMA, dim:0-1, brightness:-255-255, contrast:PERCENT, \
+                   saturation:FACTOR, grayscale, tint:0-1[:COLOR] (defaults to --color)")
+            .multiple(true)
+            .number_of_values(1)
+            .requires("image"))
+        .arg(Arg::with_name("watermark")
+            .long("watermark")
+            .value_name("[OUTPUT=]FILE|none")
+            .help("Draw an image, e.g. a logo, over the background. OUTPUT limits this \
+                   to one output: its index, 'portrait' or 'landscape'")
+            .multiple(true)
+            .number_of_values(1))
+        .arg(Arg::with_name("watermark-position")
+            .long("watermark-position")
+            .value_name("[OUTPUT=]POSITION")
+            .help("Where the watermark goes, like --text-position. Defaults to \
+                   bottom-right")
+            .multiple(true)
+            .number_of_values(1)
+            .requires("watermark"))
+        .arg(Arg::with_name("watermark-scale")
+            .long("watermark-scale")
+            .value_name("[OUTPUT=]FACTOR")
+            .help("Scale the watermark relative to its own size, e.g. 0.5")
+            .multiple(true)
+            .number_of_values(1)
+            .requires("watermark"))
+        .arg(Arg::with_name("watermark-opacity")
+            .long("watermark-opacity")
+            .value_name("[OUTPUT=]0-1")
+            .help("Opacity of the watermark. Defaults to 1")
+            .multiple(true)
+            .number_of_values(1)
+            .requires("watermark"))
+        .arg(Arg::with_name("text")
+            .long("text")
+            .value_name("TEMPLATE")
+            .help("Draw a line of text over the background, repeat it for more lines. \
+                   Fields in braces are filled in: {hostname}, {ip}, {user}, and {time} or \
+                   {time:FORMAT} with a strftime format, e.g. '{time:%A %H:%M}'. Text \
+                   with the time is redrawn as it changes")
+            .multiple(true)
+            .number_of_values(1))
+        .arg(Arg::with_name("text-position")
+            .long("text-position")
+            .value_name("POSITION")
+            .help("Where the text goes: top-left, top, top-right, left, center, right, \
+                   bottom-left, bottom or bottom-right (default)")
+            .requires("text"))
+        .arg(Arg::with_name("text-size")
+            .long("text-size")
+            .value_name("PIXELS")
+            .help("Font size of the text, which is kept as far from the edges. \
+                   Defaults to 24")
+            .requires("text"))
+        .arg(Arg::with_name("text-color")
+            .long("text-color")
+            .value_name("COLOR")
+            .help("Color of the text, like --color. Defaults to white")
+            .requires("text"))
+        .subcommand(SubCommand::with_name("palette")
+            .about("Print the main colors of an image, e.g. to theme panels and terminals \
+                    after the wallpaper")
+            .arg(Arg::with_name("colors")
+                .long("colors")
+                .value_name("COUNT")
+                .help("How many colors to pick, at most. Defaults to 8"))
+            .arg(Arg::with_name("method")
+                .long("method")
+                .value_name("METHOD")
+                .help("How the colors are picked: k-means (default) or median-cut, which \
+                       is faster but fits the image less closely"))
+            .arg(Arg::with_name("format")
+                .long("format")
+                .value_name("FORMAT")
+                .help("How the palette is printed: json (default), or shell for \
+                       color0='#rrggbb' lines, the most common color first"))
+            .arg(Arg::with_name("IMAGE")
+                .help("The image to take the colors of, defaults to --image")))
+}
+
+/// Reads the options from the command line and the config file. Errors
+/// (and `--help`) are returned instead of exiting, so a reload can keep
+/// the settings it had.
+fn parse_args() -> clap::Result<ArgMatches<'static>> {
+    let args: Vec<String> = env::args().collect();
+    match config::path_from_args(&args) {
+        Some(path) => {
+            let entries = config::read(&path)
+                .map_err(|err| clap::Error::with_description(&err, clap::ErrorKind::Io))?;
+            let mut config_args = vec![args[0].clone()];
+            config_args.extend(config::to_args(entries.into_iter()
+                .filter(|&(ref key, _)| !config::given_in_args(&args, key, SHORT_OPTIONS))));
+            config_args.extend(args[1..].iter().cloned());
+            app().get_matches_from_safe(config_args)
+        },
+        None => app().get_matches_from_safe(args)
+    }
+}
+
+/// What the options ask for. They are read again on `SIGUSR1`, which also
+/// resolves the palette colors again.
+struct Settings {
+    content: Content,
+    options: RenderOptions,
+    transition: Transition,
+    schedule: Option<Schedule>,
+    buffer_format: BufferFormat,
+}
+
+impl Settings {
+    /// What to show right now.
+    fn current(&self) -> Content {
+        match self.schedule {
+            Some(ref schedule) => schedule.current(Local::now()).content.clone(),
+            None => self.content.clone()
+        }
+    }
+}
+
+/// Reads the settings from the options. `previous` are the settings before
+/// a reload: standard input can't be read twice, so its image is kept.
+fn read_settings(matches: &ArgMatches, previous: Option<&Settings>) -> Result<Settings, String> {
+    let color = match matches.value_of("color") {
+        Some(color) => color::parse(color)?,
+        None => Rgba { data: [0x33, 0x33, 0x33, 255] }
+    };
+    let backdrop = match matches.value_of("gradient") {
+        Some(gradient) => {
+            let mut gradient = gradient.parse::<Gradient>()?;
+            if let Some(angle) = matches.value_of("gradient-angle") {
+                gradient.set_angle(parse_value(angle, "gradient angle")?);
+            }
+            Backdrop::Gradient(gradient)
         },
-        (Some(color), None) => {
-            let color: Color = color.parse::<u32>()
-                .map(|c| c.into())
-                .unwrap_or_else(|_| {
-                    let c = u32::from_str_radix(color, 16).expect("Could not parse color");
-                    c.into()
-                });
-            (color, None)
+        None => match matches.value_of("pattern") {
+            Some(pattern) => {
+                let mut pattern = pattern.parse::<Pattern>()?;
+                if let Some(size) = matches.value_of("pattern-size") {
+                    pattern.size = parse_value(size, "pattern size")?;
+                }
+                if let Some(angle) = matches.value_of("pattern-angle") {
+                    pattern.angle = parse_value(angle, "pattern angle")?;
+                }
+                if let Some(seed) = matches.value_of("seed") {
+                    pattern.seed = parse_value(seed, "seed")?;
+                }
+                Backdrop::Pattern(pattern)
+            },
+            None => Backdrop::Color(color)
         }
     };
+    let mut tone_mapping = ToneMapping::default();
+    if let Some(operator) = matches.value_of("tone-map") {
+        tone_mapping.operator = operator.parse()?;
+    }
+    if let Some(exposure) = matches.value_of("exposure") {
+        tone_mapping.exposure = parse_value(exposure, "exposure")?;
+    }
+    let has_backdrop = matches.is_present("color") || matches.is_present("gradient")
+        || matches.is_present("pattern");
+    let content = match (matches.value_of("image"), has_backdrop) {
+        (None, false) => Content::Official,
+        (Some("-"), _) if previous.is_some() => previous.unwrap().content.clone(),
+        (Some(path), _) if path == "-" || matches.is_present("raw") => {
+            let data = load::read(path)
+                .map_err(|err| format!("Could not read {}: {}", path, err))?;
+            let image = match matches.value_of("raw") {
+                Some(raw) => raw.parse::<RawImage>()?.decode(&data),
+                None => load::decode(&data, path, &tone_mapping)
+            }.map_err(|err| format!("Could not decode {}: {}", path, err))?;
+            let name = if path == "-" { "stdin" } else { path };
+            Content::Loaded { name: name.to_string(), image: Rc::new(image) }
+        },
+        (Some(image), _) => Content::Image(image.to_string()),
+        (None, true) => Content::Backdrop(backdrop.clone())
+    };
+    let mode = match matches.value_of("mode") {
+        Some(mode) => mode.parse::<BackgroundMode>()?,
+        None => BackgroundMode::Fill
+    };
+    let mut mode_options = ModeOptions::default();
+    if let Some(padding) = matches.value_of("padding") {
+        mode_options.padding = padding.parse()?;
+    }
+    if let Some(focus) = matches.value_of("focus") {
+        mode_options.focus = mode::parse_focus(focus)?;
+    }
+    if let Some(zoom) = matches.value_of("zoom") {
+        mode_options.zoom = parse_value(zoom, "zoom")?;
+    }
+    if let Some(scale) = matches.value_of("tile-scale") {
+        mode_options.tile.scale = parse_value(scale, "tile scale")?;
+    }
+    if let Some(offset) = matches.value_of("tile-offset") {
+        mode_options.tile.set_offset(offset)?;
+    }
+    if let Some(repeat) = matches.value_of("tile-repeat") {
+        mode_options.tile.repeat = repeat.parse()?;
+    }
+    let mut transforms = OutputTransforms::default();
+    for rotation in matches.values_of("rotate").into_iter().flat_map(|values| values) {
+        transforms.add_rotation(rotation)?;
+    }
+    for flip in matches.values_of("flip").into_iter().flat_map(|values| values) {
+        transforms.add_flip(flip)?;
+    }
+    let mut profiles = OutputProfiles::default();
+    for profile in matches.values_of("output-profile").into_iter().flat_map(|values| values) {
+        profiles.add(profile)?;
+    }
+    let filters = matches.values_of("filter").into_iter()
+        .flat_map(|values| values)
+        .map(|filter| Filter::parse(filter, color))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut watermarks = OutputWatermarks::default();
+    for watermark in matches.values_of("watermark").into_iter().flat_map(|values| values) {
+        watermarks.add_image(watermark, &tone_mapping)?;
+    }
+    for position in matches.values_of("watermark-position").into_iter().flat_map(|values| values) {
+        watermarks.add_position(position)?;
+    }
+    for scale in matches.values_of("watermark-scale").into_iter().flat_map(|values| values) {
+        watermarks.add_scale(scale)?;
+    }
+    for opacity in matches.values_of("watermark-opacity").into_iter().flat_map(|values| values) {
+        watermarks.add_opacity(opacity)?;
+    }
+    let text = match matches.values_of("text") {
+        Some(lines) => {
+            let lines = lines.map(|line| line.parse::<Template>())
+                .collect::<Result<Vec<_>, _>>()?;
+            let mut text = TextOverlay::new(lines);
+            if let Some(position) = matches.value_of("text-position") {
+                text.anchor = position.parse()?;
+            }
+            if let Some(size) = matches.value_of("text-size") {
+                text.size = parse_value(size, "text size")?;
+            }
+            if let Some(color) = matches.value_of("text-color") {
+                text.color = color::parse(color)?;
+            }
+            Some(text)
+        },
+        None => None
+    };
+    let dither = match matches.value_of("dither") {
+        Some(dither) => dither.parse()?,
+        None => Dither::None
+    };
+    let buffer_format = match matches.value_of("buffer-format") {
+        Some(format) => format.parse()?,
+        None => BufferFormat::Auto
+    };
+    let options = RenderOptions {
+        mode: mode,
+        mode_options: mode_options,
+        transforms: transforms,
+        filters: filters,
+        tone_mapping: tone_mapping,
+        dither: dither,
+        deep: buffer_format == BufferFormat::Deep,
+        linear_scaling: matches.is_present("linear-scaling"),
+        profiles: profiles,
+        backdrop: if matches.is_present("transparent") {
+            None
+        } else {
+            Some(backdrop)
+        },
+        watermarks: watermarks,
+        text: text,
+    };
+    let mut transition = Transition::default();
+    if let Some(kind) = matches.value_of("transition") {
+        transition.kind = kind.parse()?;
+    }
+    if let Some(easing) = matches.value_of("transition-easing") {
+        transition.easing = easing.parse()?;
+    }
+    if let Some(duration) = matches.value_of("transition-duration") {
+        let seconds: f64 = parse_value(duration, "transition duration")?;
+        if seconds < 0.0 {
+            return Err("Transition duration can't be negative".into())
+        }
+        transition.duration = Duration::from_millis((seconds * 1000.0) as u64);
+    }
+    let schedule = match matches.value_of("schedule") {
+        Some(path) => Some(Schedule::read(path)?),
+        None => None
+    };
+    Ok(Settings {
+        content: content,
+        options: options,
+        transition: transition,
+        schedule: schedule,
+        buffer_format: buffer_format,
+    })
+}
+
+/// Parses the value of an option, naming the option if it is invalid.
+fn parse_value<T>(value: &str, name: &str) -> Result<T, String>
+    where T: FromStr, T::Err: Display
+{
+    value.parse().map_err(|err| format!("Invalid {} '{}': {}", name, value, err))
+}
+
+/// Renders what every background shows next, and titles them after it.
+fn render_all(backgrounds: &mut [Background], content: &Content, options: &RenderOptions) -> Vec<Shown> {
+    backgrounds.iter_mut().map(|background| {
+        background.shell_surface.set_title(content.title());
+        let base = render::render_base(content, options, background.index, background.resolution);
+        background.finished = render::finish(base, options, background.index, background.resolution);
+        Shown::finished(&background.finished)
+    }).collect()
+}
+
+/// When the text over the backgrounds changes next, if it shows the time.
+fn next_update(options: &RenderOptions) -> Option<DateTime<Local>> {
+    options.text.as_ref().and_then(|text| text.next_update(Local::now()))
+}
+
+/// Prints the palette of the image given to the `palette` subcommand.
+fn print_palette(matches: &ArgMatches, palette: &ArgMatches) -> Result<(), String> {
+    let path = palette.value_of("IMAGE").or(matches.value_of("image"))
+        .ok_or("No image to take the colors of, give one or set --image")?;
+    let count = match palette.value_of("colors") {
+        Some(count) => parse_value::<usize>(count, "number of colors")?,
+        None => 8
+    };
+    let method = match palette.value_of("method") {
+        Some(method) => method.parse()?,
+        None => Method::KMeans
+    };
+    let output = match palette.value_of("format") {
+        Some(format) => format.parse()?,
+        None => Output::Json
+    };
+    let data = load::read(path).map_err(|err| format!("Could not read {}: {}", path, err))?;
+    let image = if svg::is_svg(&data) {
+        // Vector images are only decoded from their source, at their own
+        // size.
+        let svg = svg::Svg::parse(&data).map_err(|err| format!("{}: {}", path, err))?;
+        let (width, height) = svg.size();
+        svg.rasterize(width.round() as u32, height.round() as u32)
+    } else {
+        let image = load::decode(&data, path, &ToneMapping::default())
+            .map_err(|err| format!("Could not decode {}: {}", path, err))?;
+        precise::quantize(&image, Dither::None)
+    };
+    let swatches = quantize::palette(&image, count, method);
+    print!("{}", quantize::format(&swatches, output));
+    Ok(())
+}
+
+fn main() {
+    let matches = parse_args().unwrap_or_else(|err| err.exit());
+    if let Some(palette) = matches.subcommand_matches("palette") {
+        print_palette(&matches, palette).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            ::std::process::exit(1);
+        });
+        return
+    }
+    let mut settings = read_settings(&matches, None).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        ::std::process::exit(1);
+    });
+    let reload = Reload::listen().expect("Could not listen for SIGUSR1");
 
     let (display, mut event_queue) = wayland_client::default_connect()
         .expect("Unable to connect to a wayland compositor");
@@ -169,6 +732,18 @@ fn main() {
     let pointer = seat.get_pointer().expect("Could not get pointer from seat global");
     let shell = get_wayland!(env_id, &registry, &mut event_queue, WlShell, "wl_shell").unwrap();
     let compositor = get_wayland!(env_id, &registry, &mut event_queue, WlCompositor, "wl_compositor").unwrap();
+    // The env's shm has no handler for its events, so bind another one to
+    // hear which formats the compositor supports.
+    let shm = get_wayland!(env_id, &registry, &mut event_queue, WlShm, "wl_shm").unwrap();
+    let formats_id = event_queue.add_handler(ShmFormats::new());
+    event_queue.register::<_, ShmFormats>(&shm, formats_id);
+    event_queue.sync_roundtrip().expect("Could not sync roundtrip");
+    let mut buffers = Buffers {
+        shm: shm,
+        formats_id: formats_id,
+        format: settings.buffer_format,
+        dither: settings.options.dither,
+    };
     let mut cursor_surface = compositor.create_surface();
     let _cursor_buffer = self::cursor_surface(&mut cursor_surface, &mut event_queue, env_id);
     let resolutions: Vec<usize> = outputs.iter()
@@ -178,259 +753,274 @@ fn main() {
             event_queue.register::<_, Resolution>(&output, resolution_id);
             resolution_id
         }).collect();
-    let mut bg_metadata = outputs.iter().zip(resolutions).map(|(output, res_id)| {
+    let bg_metadata: Vec<_> = outputs.iter().zip(resolutions).map(|(output, res_id)| {
         let background_surface = compositor.create_surface();
         desktop_shell.set_background(output, &background_surface);
         (output, res_id, background_surface)
-    });
+    }).collect();
     event_queue.dispatch()
         .expect("Could not dispatch queue");
-    for (output, resolution_id, mut background_surface) in &mut bg_metadata {
-        let resolution: Resolution = { *event_queue.state().get_handler(resolution_id) };
-        assert!(resolution.w * resolution.h != 0);
-        let shell_surface = shell.get_shell_surface(&background_surface);
-        shell_surface.set_class("Background".into());
-        shell_surface.set_fullscreen(FullscreenMethod::Default, 0, Some(&output));
-        shell_surface.set_maximized(Some(&output));
-        match image {
-            None => {
-                shell_surface.set_title(format!("Background Color: {}", color.to_u32()));
-
-                generate_solid_background(color, resolution, &mut event_queue, &mut background_surface, env_id)
-            },
-            Some((ref mut image, mode)) => {
-                if image.is_empty() {
-                    shell_surface.set_title("Official background".into());
-                } else {
-                    shell_surface.set_title(format!("Background Image: {}", image));
-                }
-
-                generate_image_background(image.as_ref(),
-                                          resolution,
-                                          &mut event_queue,
-                                          mode,
-                                          color,
-                                          &mut background_surface,
-                                          env_id)
-            }
-        }.expect("could not generate image");
+    let content = settings.current();
+    let mut backgrounds: Vec<Background> = bg_metadata.into_iter().enumerate()
+        .map(|(index, (output, resolution_id, background_surface))| {
+            let resolution: Resolution = { *event_queue.state().get_handler(resolution_id) };
+            assert!(resolution.w * resolution.h != 0);
+            let shell_surface = shell.get_shell_surface(&background_surface);
+            shell_surface.set_class("Background".into());
+            shell_surface.set_fullscreen(FullscreenMethod::Default, 0, Some(&output));
+            shell_surface.set_maximized(Some(&output));
+            background_surface.set_buffer_scale(1);
+            let frame_id = event_queue.add_handler(FrameHandler::new());
+            let base = render::render_base(&content, &settings.options, index, resolution);
+            let finished = render::finish(base, &settings.options, index, resolution);
+            let mut background = Background {
+                index: index,
+                resolution: resolution,
+                surface: background_surface,
+                shell_surface: shell_surface,
+                pool: None,
+                frame_id: frame_id,
+                frame_callback: None,
+                shown: Shown::new(RgbaImage::new(0, 0)),
+                finished: finished,
+            };
+            background.shell_surface.set_title(content.title());
+            let shown = Shown::finished(&background.finished);
+            background.show(shown, &mut event_queue, &buffers);
+            background
+        }).collect();
 
-        background_surface.commit();
-        background_surface.set_buffer_scale(1);
-    }
+    let mut next_change = settings.schedule.as_ref().map(|schedule| schedule.next_change(Local::now()));
+    let mut next_update = self::next_update(&settings.options);
+    let mut changing: Option<Changing> = None;
     loop {
+        // While changing, frames are drawn as frame callbacks arrive, the
+        // deadline only makes sure the last one is shown.
+        let deadline = match changing {
+            Some(ref changing) => Some(changing.end()),
+            None => next_change.into_iter().chain(next_update).min().map(|change| {
+                Instant::now() + (change - Local::now()).to_std().unwrap_or(Duration::from_secs(0))
+            })
+        };
         display.flush()
             .expect("Could not flush display");
-        event_queue.dispatch()
+        dispatch::dispatch_until(&display, &mut event_queue, deadline, reload.fd())
             .expect("Could not dispatch queue");
         pointer.set_cursor(0, Some(&cursor_surface), 0, 0)
             .expect("Could not set cursor");
-    }
-}
 
-fn rgba_conversion(num: u8, third_num: u32) -> u8 {
-    let big_num = num as u32;
-    ((big_num * third_num) / 255) as u8
-}
-
-/// Given a solid color, writes bytes associated with that color to
-/// a special Wayland surface which is then rendered as a background for Way Cooler.
-fn generate_solid_background(color: Color,
-                             resolution: Resolution,
-                             event_queue: &mut wayland_client::EventQueue,
-                             background_surface: &mut wl_surface::WlSurface,
-                             env_id: usize) -> BufferResult {
-    // Get shortcuts to the globals.
-    let state = event_queue.state();
-    let env = state.get_handler::<EnvHandler<WaylandEnv>>(env_id);
-    let shm = &env.shm;
+        // Invalid options on a reload keep the settings from before.
+        if reload.requested() {
+            let reloaded = parse_args()
+                .map_err(|err| err.message)
+                .and_then(|matches| read_settings(&matches, Some(&settings)));
+            match reloaded {
+                Ok(reloaded) => {
+                    settings = reloaded;
+                    buffers.format = settings.buffer_format;
+                    buffers.dither = settings.options.dither;
+                    let targets = render_all(&mut backgrounds, &settings.current(), &settings.options);
+                    changing = Some(Changing::new(settings.transition, &backgrounds, targets));
+                    next_change = settings.schedule.as_ref()
+                        .map(|schedule| schedule.next_change(Local::now()));
+                    next_update = self::next_update(&settings.options);
+                },
+                Err(err) => eprintln!("Could not reload the settings: {}", err)
+            }
+        }
 
-    // Create the surface we are going to write into
-    let mut tmp = tempfile::tempfile().ok().expect("Unable to create a tempfile.");
+        if let (Some(schedule), Some(change)) = (settings.schedule.as_ref(), next_change) {
+            let now = Local::now();
+            if now >= change {
+                let entry = schedule.current(now);
+                let targets = render_all(&mut backgrounds, &entry.content, &settings.options);
+                let mut entry_transition = settings.transition;
+                if let Some(fade) = entry.fade {
+                    entry_transition.duration = fade;
+                }
+                changing = Some(Changing::new(entry_transition, &backgrounds, targets));
+                next_change = Some(schedule.next_change(now));
+            }
+        }
 
-    // Calculate how big the buffer needs to be from the output resolution
-    let size = (resolution.w * resolution.h) as i32;
+        // The text is redrawn over the same backgrounds, after a transition
+        // if one is running.
+        if let Some(update) = next_update {
+            if changing.is_none() && Local::now() >= update {
+                let now = Local::now();
+                for background in backgrounds.iter_mut() {
+                    background.finished.redraw_text(&settings.options, background.index,
+                                                    background.resolution, now);
+                    let shown = Shown::finished(&background.finished);
+                    background.show(shown, &mut event_queue, &buffers);
+                }
+                next_update = self::next_update(&settings.options);
+            }
+        }
 
-    // Write in the color coding to the surface
-    for _ in 0..size {
-        unsafe {
-            tmp.write_u32::<NativeEndian>(transmute(color.to_u32()))
-                .expect("Could not write to file")
+        let finished = match changing {
+            Some(ref changing) => changing.step(&mut backgrounds, &mut event_queue, &buffers),
+            None => false
+        };
+        if finished {
+            changing = None;
         }
     }
-    tmp.flush()
-        .expect("Could not flush buffer");
+}
 
-    // Create the buffer that is mem-mapped to the temp file descriptor
-    let pool = shm.create_pool(tmp.as_raw_fd(), size);
-    let background_buffer = pool.create_buffer(0,
-                                               resolution.w as i32,
-                                               resolution.h as i32,
-                                               resolution.w as i32,
-                                               WlShmFormat::Argb8888)
-        .expect("Could not create buffer");
-    // Tell Way Cooler not to set put this in the tree, treat as background
+/// A background surface on one output.
+struct Background {
+    index: usize,
+    resolution: Resolution,
+    surface: wl_surface::WlSurface,
+    shell_surface: wl_shell_surface::WlShellSurface,
+    /// The buffers the background is drawn into, reused from frame to
+    /// frame.
+    pool: Option<BufferPool>,
+    /// The `FrameHandler` of the surface.
+    frame_id: usize,
+    frame_callback: Option<wl_callback::WlCallback>,
+    /// What is shown right now, kept to transition from it.
+    shown: Shown,
+    /// The latest background rendered for the output, kept to redraw the
+    /// text over it.
+    finished: Finished,
+}
 
-    // Attach the buffer to the surface
-    background_surface.attach(Some(&background_buffer), 0, 0);
-    Ok(background_buffer)
+/// A straight RGBA image with whether it is opaque, which is found once
+/// when it is rendered instead of for every buffer it is written to.
+#[derive(Clone)]
+struct Shown {
+    image: RgbaImage,
+    opaque: bool,
+    /// The image before it was rounded to 8 bits, for 10 bit buffers.
+    precise: Option<Rc<PreciseImage>>,
 }
 
-fn fill_image_base_color(image: DynamicImage, color: Color) -> DynamicImage {
-    let color = color.to_u8s();
-    let color = image::Rgb::from_channels(color.2, color.1, color.0 , color.3);
-    let buffer = if let DynamicImage::ImageRgb8(mut buffer) = image {
-        for (_, _, p) in buffer.enumerate_pixels_mut() {
-            *p = color;
-        }
-        Ok(buffer)
-    } else {
-        Err("image has wrong variant")
-    }.unwrap();
+impl Shown {
+    fn new(image: RgbaImage) -> Shown {
+        let opaque = shm::is_opaque(&image);
+        Shown { image: image, opaque: opaque, precise: None }
+    }
 
-    DynamicImage::ImageRgb8(buffer)
+    fn finished(finished: &Finished) -> Shown {
+        Shown {
+            precise: finished.precise.clone().map(Rc::new),
+            ..Shown::new(finished.image.clone())
+        }
+    }
 }
 
-/// Given the data from an image, writes it to a special Wayland surface
-/// which is then rendered as a background for Way Cooler.
-fn generate_image_background(path: &str,
-                             resolution: Resolution,
-                             event_queue: &mut wayland_client::EventQueue,
-                             mode: BackgroundMode,
-                             color: Color,
-                             background_surface: &mut wl_surface::WlSurface,
-                             env_id: usize) -> BufferResult {
-    // TODO support more formats, split into separate function
-    let state = event_queue.state();
-    let env = state.get_handler::<EnvHandler<WaylandEnv>>(env_id);
-    let image = open(path)
-        .unwrap_or_else(|_| {
-            load_from_memory(include_bytes!("../assets/official-background.png"))
-                .expect("Could not read in official background image")
-        });
-    let (scr_width, scr_height) = (resolution.w as u32, resolution.h as u32);
-
-    let img_width = image.width();
-    let img_height = image.height();
-
-    // Mode image processing
-    // The output must be scr_width x scr_height resolution
-    let image = match mode {
-        BackgroundMode::Fill    => {
-            // Find fit scale
-            let width_sr: f64  = scr_width as f64 / img_width as f64;
-            let height_sr: f64 = scr_height as f64 / img_height as f64;
-            let scale_ratio: f64 = if width_sr > height_sr {
-                width_sr
-            } else {
-                height_sr
-            };
-            let img_width = (scale_ratio * img_width as f64) as u32;
-            let img_height = (scale_ratio * img_height as f64) as u32;
-
-            let mut image = image.resize(img_width, img_height, FilterType::Gaussian);
-            image.crop(((img_width as i32 - scr_width as i32) / 2).abs() as u32,
-                ((img_height as i32 - scr_height as i32) / 2).abs() as u32,
-                scr_width,
-                scr_height)
-        },
-        BackgroundMode::Fit     => {
-            // Find fit scale ratio
-            let width_sr: f64  = scr_width as f64 / img_width as f64;
-            let height_sr: f64 = scr_height as f64 / img_height as f64;
-            let scale_ratio: f64 = if width_sr < height_sr {
-                width_sr
-            } else {
-                height_sr
-            };
-            let img_width = (scale_ratio * img_width as f64) as u32;
-            let img_height = (scale_ratio * img_height as f64) as u32;
-
-            let image = image.resize(img_width, img_height, FilterType::Gaussian);
+impl Background {
+    /// Shows an image, damaging all of the surface.
+    fn show(&mut self,
+            shown: Shown,
+            event_queue: &mut wayland_client::EventQueue,
+            buffers: &Buffers) {
+        let format = {
+            let state = event_queue.state();
+            let formats = &state.get_handler::<ShmFormats>(buffers.formats_id).formats;
+            buffers.format.choose(shown.opaque, formats)
+        };
+        let precise = shown.precise.as_ref().map(|precise| &**precise);
+        let (pixels, stride) = shm::encode(&shown.image, precise, format, buffers.dither);
+        let (width, height) = shown.image.dimensions();
+        let layout = Layout { width: width, height: height, stride: stride, format: format };
+        // A new size or format needs new buffers, the old ones are kept
+        // until the new one is shown.
+        let fits = self.pool.as_ref().map_or(false, |pool| pool.layout() == layout);
+        let previous = if fits { None } else { self.pool.take() };
+        if self.pool.is_none() {
+            self.pool = Some(BufferPool::new(&buffers.shm, event_queue, layout));
+        }
+        let buffer = self.pool.as_mut().unwrap().write(&pixels, event_queue);
+        self.surface.attach(Some(buffer), 0, 0);
+        self.surface.damage(0, 0, width as i32, height as i32);
+        self.surface.commit();
+        if let Some(previous) = previous {
+            previous.destroy();
+        }
+        self.shown = shown;
+    }
 
-            let imagepad = DynamicImage::new_rgb8(scr_width, scr_height);
-            let mut imagepad = fill_image_base_color(imagepad, color);
-            imagepad.copy_from(&image, 0, ((scr_height - img_height) / 2) as u32);
+    /// Whether the compositor is ready for the next frame.
+    fn frame_done(&self, event_queue: &mut wayland_client::EventQueue) -> bool {
+        event_queue.state().get_handler::<FrameHandler>(self.frame_id).done
+    }
 
-            imagepad
-        },
-        BackgroundMode::Stretch => {
-            image.resize_exact(scr_width, scr_height, FilterType::Gaussian)
-        },
-        BackgroundMode::Center  => {
-            let width_diff: i32 = scr_width as i32 - img_width as i32;
-            let height_diff: i32 = scr_height as i32 - img_height as i32;
+    /// Shows a frame of a transition, asking to be told when the compositor
+    /// is ready for the next one.
+    fn show_frame(&mut self,
+                  shown: Shown,
+                  event_queue: &mut wayland_client::EventQueue,
+                  buffers: &Buffers) {
+        let callback = self.surface.frame();
+        event_queue.register::<_, FrameHandler>(&callback, self.frame_id);
+        event_queue.state().get_mut_handler::<FrameHandler>(self.frame_id).done = false;
+        self.frame_callback = Some(callback);
+        self.show(shown, event_queue, buffers);
+    }
+}
 
-            let mut image = image;
-            let image = image.sub_image(max(-width_diff, 0) as u32 / 2,
-                max(-height_diff, 0) as u32 / 2,
-                min(scr_width, img_width),
-                min(scr_height, img_height));
+/// Every background changing to a new image.
+struct Changing {
+    transition: Transition,
+    sources: Vec<Shown>,
+    targets: Vec<Shown>,
+    start: Instant,
+}
 
-            let imagepad = DynamicImage::new_rgb8(scr_width, scr_height);
-            let mut imagepad = fill_image_base_color(imagepad, color);
+impl Changing {
+    /// Starts changing from what the backgrounds show right now, which may
+    /// be the middle of another transition.
+    fn new(transition: Transition, backgrounds: &[Background], targets: Vec<Shown>) -> Changing {
+        Changing {
+            transition: transition,
+            sources: backgrounds.iter().map(|background| background.shown.clone()).collect(),
+            targets: targets,
+            start: Instant::now(),
+        }
+    }
 
-            let wpad = max(width_diff, 0) / 2;
-            let hpad = max(height_diff, 0) / 2;
-            imagepad.copy_from(&image, wpad as u32, hpad as u32);
+    fn end(&self) -> Instant {
+        self.start + self.transition.duration
+    }
 
-            imagepad
-        },
-        BackgroundMode::Tile    => {
-            let repeat_x_count: u32 = (scr_width as f64 / img_width as f64).ceil() as u32;
-            let repeat_y_count: u32 = (scr_height as f64 / img_height as f64).ceil() as u32;
-
-            let mut imagepad = DynamicImage::new_rgba8(img_width * repeat_x_count, img_height * repeat_y_count);
-            for x in 0..repeat_x_count {
-                for y in 0..repeat_y_count {
-                    imagepad.copy_from(&image, x * img_width, y * img_height);
-                }
+    /// Shows the next frame on every background that is ready for one,
+    /// returns whether the transition finished.
+    fn step(&self,
+            backgrounds: &mut [Background],
+            event_queue: &mut wayland_client::EventQueue,
+            buffers: &Buffers) -> bool {
+        let t = self.transition.fraction(self.start.elapsed());
+        for ((background, source), target) in backgrounds.iter_mut()
+            .zip(&self.sources)
+            .zip(&self.targets) {
+            if t >= 1.0 {
+                background.show(target.clone(), event_queue, buffers);
+            } else if background.frame_done(event_queue) {
+                // Every frame is made of the pixels of both images, it is
+                // opaque if they are.
+                let frame = Shown {
+                    image: self.transition.frame(&source.image, &target.image, t),
+                    opaque: source.opaque && target.opaque,
+                    precise: None,
+                };
+                background.show_frame(frame, event_queue, buffers);
             }
-            imagepad.crop(0, 0, scr_width, scr_height)
-        },
-    };
-
-    let img_height = scr_height;
-    let img_width = scr_width;
-    let img_stride = img_width * 4;
-    let img_size = img_stride * img_height;
-
-    let mut image_rgba = image.to_rgba();
-
-    // TODO Split this into its own function
-    {
-        let pixels = image_rgba.enumerate_pixels_mut();
-        for (_x, _y, pixel) in pixels {
-            let alpha = pixel[3] as u32;
-            pixel[0] = rgba_conversion(pixel[0], alpha);
-            pixel[1] = rgba_conversion(pixel[1], alpha);
-            pixel[2] = rgba_conversion(pixel[2], alpha);
-
-            let tmp = pixel[2];
-            pixel[2] = pixel[0];
-            pixel[0] = tmp;
         }
+        t >= 1.0
     }
+}
 
-    let mut tmp = tempfile::NamedTempFile::new().expect("Unable to create a tempfile.");
-    tmp.set_len(img_size as u64).expect("Could not truncate length of file");
-    tmp.write_all(&image_rgba).unwrap();
-
-    let shm = &env.shm;
-
-    // Create the surface we are going to write into
-    let pool = shm.create_pool(tmp.as_raw_fd(), img_size as i32);
-    let background_buffer = pool.create_buffer(0,
-                                               scr_width as i32,
-                                               scr_height as i32,
-                                               img_stride as i32,
-                                               WlShmFormat::Argb8888)
-        .expect("Could not create buffer");
-
-    // Attach the buffer to the surface
-    background_surface.attach(Some(&background_buffer), 0, 0);
-    background_surface.damage(0, 0, scr_width as i32, scr_height as i32);
-    Ok(background_buffer)
+/// How the buffers of the backgrounds are created.
+struct Buffers {
+    shm: wl_shm::WlShm,
+    /// The handler collecting the formats the compositor supports.
+    formats_id: usize,
+    format: BufferFormat,
+    /// How formats with less than 8 bits per channel are rounded.
+    dither: Dither,
 }
 
 fn cursor_surface(cursor_surface: &mut wl_surface::WlSurface,
@@ -442,25 +1032,13 @@ fn cursor_surface(cursor_surface: &mut wl_surface::WlSurface,
 
     let image = load_from_memory(CURSOR)
         .expect("Could not read cursor data, report to maintainer!");
-    let mut image = image.to_rgba();
+    let image = image.to_rgba();
     let width = image.width();
     let height = image.height();
     let stride = width * 4;
     let size = stride * height;
-    {
-        let pixels = image.enumerate_pixels_mut();
-        for (_x, _y, pixel) in pixels {
-            let alpha = pixel[3] as u32;
-            pixel[0] = rgba_conversion(pixel[0], alpha);
-            pixel[1] = rgba_conversion(pixel[1], alpha);
-            pixel[2] = rgba_conversion(pixel[2], alpha);
-
-            let tmp = pixel[2];
-            pixel[2] = pixel[0];
-            pixel[0] = tmp;
-        }
-    }
-    let vec = image.into_vec();
+    let mut vec = image.into_vec();
+    convert::rgba_to_argb_premultiplied(&mut vec);
     let mut tmp = tempfile::NamedTempFile::new().expect("Unable to create a tempfile.");
     tmp.set_len(size as u64).expect("Could not truncate length of file");
     tmp.write_all(&*vec).unwrap();
@@ -470,11 +1048,3 @@ fn cursor_surface(cursor_surface: &mut wl_surface::WlSurface,
     cursor_surface.attach(Some(&cursor_buffer), 0, 0);
     Ok(cursor_buffer)
 }
-
-
-#[test]
-fn test_rgba_conversion() {
-    assert_eq!(rgba_conversion(10, 254), 9);
-    assert_eq!(rgba_conversion(2, 255), 2);
-    assert_eq!(rgba_conversion(255, 500), 500);
-}
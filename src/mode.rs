@@ -0,0 +1,376 @@
+//! Scaling and placing the image on an output according to the
+//! `BackgroundMode`.
+
+use std::cmp::{min, max};
+use std::str::FromStr;
+
+use image::{imageops, FilterType, ImageBuffer, Rgba};
+
+use backdrop;
+use dither::Dither;
+use filter::Filter;
+use precise::{self, PreciseImage};
+use quantize;
+use resample::{self, Resampling};
+use tile::{self, TileOptions};
+
+#[derive(Debug, Clone, Copy)]
+pub enum BackgroundMode {
+    /// Scale image to make the shortest dimension (i.e. height or width)
+    /// fit it's container pertaining aspect ratio.
+    Fill,
+
+    /// Scale image width to fit container width pertaining aspect ratio.
+    Fit,
+
+    /// Scale height and width to fit container's. May create distortion.
+    Stretch,
+
+    /// Do not scale image and place to center.
+    Center,
+
+    /// Create repeated image forming tile-pattern, see `TileOptions`.
+    Tile,
+}
+
+impl FromStr for BackgroundMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<BackgroundMode, String> {
+        match s {
+            "fill"    => Ok(BackgroundMode::Fill),
+            "fit"     => Ok(BackgroundMode::Fit),
+            "stretch" => Ok(BackgroundMode::Stretch),
+            "center"  => Ok(BackgroundMode::Center),
+            "tile"    => Ok(BackgroundMode::Tile),
+            _         => Err(format!("Invalid mode: {}", s)),
+        }
+    }
+}
+
+/// What fills the area the fit and center modes leave around the image.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Padding {
+    /// Leave it transparent, so the background color shows.
+    Backdrop,
+
+    /// The most common color of the image, see `quantize::dominant`.
+    Dominant,
+
+    /// A blurred and darkened copy of the image, scaled like the fill mode.
+    Blur {
+        /// Standard deviation of the blur, relative to the output's largest
+        /// dimension.
+        radius: f32,
+        /// How much to darken it, between 0 and 1.
+        dim: f32,
+    },
+}
+
+impl FromStr for Padding {
+    type Err = String;
+
+    /// Parses `color`, `dominant` or `blur[:RADIUS[:DIM]]`.
+    fn from_str(s: &str) -> Result<Padding, String> {
+        let mut parts = s.split(':');
+        match parts.next() {
+            Some("color") => Ok(Padding::Backdrop),
+            Some("dominant") => Ok(Padding::Dominant),
+            Some("blur") => {
+                let mut number = |default: f32| parts.next()
+                    .map(|value| value.parse::<f32>()
+                         .map_err(|_| format!("Invalid padding argument: {}", value)))
+                    .unwrap_or(Ok(default));
+                let radius = number(0.02)?;
+                let dim = number(0.4)?;
+                if dim < 0.0 || dim > 1.0 {
+                    return Err("Padding dim must be between 0 and 1".into())
+                }
+                Ok(Padding::Blur { radius: radius, dim: dim })
+            },
+            _ => Err(format!("Invalid padding: {}", s)),
+        }
+    }
+}
+
+/// Settings for how the modes scale and place the image.
+#[derive(Debug, Clone, Copy)]
+pub struct ModeOptions {
+    pub padding: Padding,
+    /// The point the fill mode crops around, relative to the image size.
+    pub focus: (f64, f64),
+    /// How much further the fill mode zooms into the image, at least 1.
+    pub zoom: f64,
+    pub tile: TileOptions,
+}
+
+impl Default for ModeOptions {
+    fn default() -> ModeOptions {
+        ModeOptions {
+            padding: Padding::Backdrop,
+            focus: (0.5, 0.5),
+            zoom: 1.0,
+            tile: TileOptions::default(),
+        }
+    }
+}
+
+/// Parses a focus point such as `0.3,0.2`.
+pub fn parse_focus(s: &str) -> Result<(f64, f64), String> {
+    let coordinates = s.split(',')
+        .map(|c| c.trim().parse::<f64>().ok().filter(|c| *c >= 0.0 && *c <= 1.0))
+        .collect::<Option<Vec<_>>>();
+    match coordinates {
+        Some(ref c) if c.len() == 2 => Ok((c[0], c[1])),
+        _ => Err(format!("Focus must be two numbers between 0 and 1, e.g. 0.5,0.3: {}", s)),
+    }
+}
+
+/// Scales and places the image according to the mode, resampling it as
+/// set by `resampling`.
+/// The result is always `scr_width` x `scr_height`.
+pub fn apply(mode: BackgroundMode,
+             image: PreciseImage,
+             scr_width: u32,
+             scr_height: u32,
+             options: &ModeOptions,
+             resampling: Resampling) -> PreciseImage {
+    let img_width = image.width();
+    let img_height = image.height();
+
+    match mode {
+        BackgroundMode::Fill    => fill(&image, scr_width, scr_height,
+                                        options.focus, options.zoom, resampling),
+        BackgroundMode::Fit     => {
+            // Find fit scale ratio
+            let width_sr: f64  = scr_width as f64 / img_width as f64;
+            let height_sr: f64 = scr_height as f64 / img_height as f64;
+            let scale_ratio: f64 = if width_sr < height_sr {
+                width_sr
+            } else {
+                height_sr
+            };
+            let img_width = (scale_ratio * img_width as f64) as u32;
+            let img_height = (scale_ratio * img_height as f64) as u32;
+
+            let resized = resize_exact(&image, img_width, img_height, resampling);
+
+            let mut imagepad = padding(&image, scr_width, scr_height, options, resampling);
+            backdrop::composite_at(&mut imagepad,
+                                   &resized,
+                                   (scr_width - img_width) / 2,
+                                   (scr_height - img_height) / 2);
+
+            imagepad
+        },
+        BackgroundMode::Stretch => resize_exact(&image, scr_width, scr_height, resampling),
+        BackgroundMode::Center  => {
+            let width_diff: i32 = scr_width as i32 - img_width as i32;
+            let height_diff: i32 = scr_height as i32 - img_height as i32;
+
+            let mut imagepad = padding(&image, scr_width, scr_height, options, resampling);
+
+            let mut image = image;
+            let image = imageops::crop(&mut image,
+                max(-width_diff, 0) as u32 / 2,
+                max(-height_diff, 0) as u32 / 2,
+                min(scr_width, img_width),
+                min(scr_height, img_height)).to_image();
+
+            let wpad = max(width_diff, 0) / 2;
+            let hpad = max(height_diff, 0) / 2;
+            backdrop::composite_at(&mut imagepad, &image, wpad as u32, hpad as u32);
+
+            imagepad
+        },
+        BackgroundMode::Tile    => tile::tile(&image, scr_width, scr_height, &options.tile,
+                                              resampling),
+    }
+}
+
+/// Scales the image so it covers the whole output and crops what sticks out.
+///
+/// The crop keeps the `focus` point, given relative to the image size, as
+/// close to the center of the output as possible. A `zoom` above 1 crops
+/// that much further into the image.
+fn fill(image: &PreciseImage, scr_width: u32, scr_height: u32,
+        focus: (f64, f64), zoom: f64, resampling: Resampling) -> PreciseImage {
+    let img_width = image.width();
+    let img_height = image.height();
+
+    // Find fit scale
+    let width_sr: f64  = scr_width as f64 / img_width as f64;
+    let height_sr: f64 = scr_height as f64 / img_height as f64;
+    let scale_ratio: f64 = if width_sr > height_sr {
+        width_sr
+    } else {
+        height_sr
+    };
+    let scale_ratio = scale_ratio * zoom.max(1.0);
+
+    // The part of the image that ends up on screen, in image coordinates.
+    let crop_width = (scr_width as f64 / scale_ratio).round().max(1.0).min(img_width as f64);
+    let crop_height = (scr_height as f64 / scale_ratio).round().max(1.0).min(img_height as f64);
+    let crop_x = (focus.0 * img_width as f64 - crop_width / 2.0)
+        .max(0.0).min(img_width as f64 - crop_width);
+    let crop_y = (focus.1 * img_height as f64 - crop_height / 2.0)
+        .max(0.0).min(img_height as f64 - crop_height);
+
+    let mut image = image.clone();
+    let cropped = imageops::crop(&mut image, crop_x.round() as u32, crop_y.round() as u32,
+                                 crop_width as u32, crop_height as u32).to_image();
+    resize_exact(&cropped, scr_width, scr_height, resampling)
+}
+
+/// Resizes the image, unless it already has the size. Resampling to the
+/// same size would still blur it, e.g. vector images rendered at the
+/// output's size.
+fn resize_exact(image: &PreciseImage, width: u32, height: u32,
+                resampling: Resampling) -> PreciseImage {
+    if image.dimensions() == (width, height) {
+        image.clone()
+    } else {
+        resample::resize_precise(image, width, height, FilterType::Gaussian, resampling)
+    }
+}
+
+/// The size an image of `img_width` x `img_height` is scaled to before it
+/// is placed on the output. Vector images are rendered at this size, so
+/// `apply` places them without scaling them again.
+///
+/// The tile mode scales by `options.tile.scale`, which has to be reset to 1
+/// for an image rendered at this size.
+pub fn target_size(mode: BackgroundMode,
+                   img_width: f64,
+                   img_height: f64,
+                   scr_width: u32,
+                   scr_height: u32,
+                   options: &ModeOptions) -> (u32, u32) {
+    let (scr_width, scr_height) = (scr_width as f64, scr_height as f64);
+    let width_sr = scr_width / img_width;
+    let height_sr = scr_height / img_height;
+    // The side that limits the scale matches the output exactly, the
+    // other one is rounded.
+    let (width, height) = match mode {
+        BackgroundMode::Fill => if options.zoom <= 1.0 {
+            if width_sr > height_sr {
+                (scr_width, img_height * width_sr)
+            } else {
+                (img_width * height_sr, scr_height)
+            }
+        } else {
+            let scale = width_sr.max(height_sr) * options.zoom;
+            (img_width * scale, img_height * scale)
+        },
+        BackgroundMode::Fit => if width_sr < height_sr {
+            (scr_width, img_height * width_sr)
+        } else {
+            (img_width * height_sr, scr_height)
+        },
+        BackgroundMode::Stretch => (scr_width, scr_height),
+        BackgroundMode::Center => (img_width, img_height),
+        BackgroundMode::Tile => (img_width * options.tile.scale, img_height * options.tile.scale),
+    };
+    (width.round().max(1.0) as u32, height.round().max(1.0) as u32)
+}
+
+/// The canvas the fit and center modes draw the image on.
+fn padding(image: &PreciseImage, scr_width: u32, scr_height: u32,
+           options: &ModeOptions, resampling: Resampling) -> PreciseImage {
+    match options.padding {
+        Padding::Backdrop => ImageBuffer::new(scr_width, scr_height),
+        Padding::Dominant => match quantize::dominant(&precise::quantize(image, Dither::None)) {
+            Some(color) => ImageBuffer::from_pixel(scr_width, scr_height, Rgba {
+                data: [color[0] as f32, color[1] as f32, color[2] as f32, 255.0]
+            }),
+            None => ImageBuffer::new(scr_width, scr_height),
+        },
+        Padding::Blur { radius, dim } => {
+            // Blurring at full resolution is slow and the detail is thrown
+            // away anyway, so blur a small copy and scale it back up.
+            const DOWNSCALE: u32 = 8;
+            let (small_width, small_height) = (max(scr_width / DOWNSCALE, 1),
+                                               max(scr_height / DOWNSCALE, 1));
+            let small = fill(image, small_width, small_height, options.focus, options.zoom,
+                             resampling);
+            let sigma = radius * max(small_width, small_height) as f32;
+            let blurred = Filter::Blur(sigma).apply(small);
+            let dimmed = Filter::Dim(dim).apply(blurred);
+            resample::resize_precise(&dimmed, scr_width, scr_height, FilterType::Triangle,
+                                     resampling)
+        },
+    }
+}
+
+#[test]
+fn test_padding() {
+    assert_eq!("color".parse(), Ok(Padding::Backdrop));
+    assert_eq!("dominant".parse(), Ok(Padding::Dominant));
+    assert_eq!("blur".parse(), Ok(Padding::Blur { radius: 0.02, dim: 0.4 }));
+    assert_eq!("blur:0.05".parse(), Ok(Padding::Blur { radius: 0.05, dim: 0.4 }));
+    assert_eq!("blur:0.05:0.3".parse(), Ok(Padding::Blur { radius: 0.05, dim: 0.3 }));
+    assert!("blur:0.05:2".parse::<Padding>().is_err());
+    assert!("blur:wide".parse::<Padding>().is_err());
+    assert!("mirror".parse::<Padding>().is_err());
+    assert!("".parse::<Padding>().is_err());
+}
+
+#[test]
+fn test_fill_focus() {
+    use image::RgbaImage;
+
+    // Each column and row has its own color.
+    let image = precise::from_rgba(&RgbaImage::from_fn(40, 10, |x, y| {
+        Rgba { data: [x as u8 * 6, y as u8 * 20, 0, 255] }
+    }));
+    let filled = |focus, zoom| precise::quantize(
+        &fill(&image, 10, 10, focus, zoom, Resampling::default()), Dither::None);
+
+    // At the same scale the crop is copied as it is.
+    assert_eq!(filled((0.0, 0.5), 1.0).get_pixel(0, 0).data, [0, 0, 0, 255]);
+    assert_eq!(filled((0.5, 0.5), 1.0).get_pixel(0, 0).data, [15 * 6, 0, 0, 255]);
+    assert_eq!(filled((1.0, 0.5), 1.0).get_pixel(9, 9).data, [39 * 6, 180, 0, 255]);
+    // Zooms below 1 would leave gaps, they are ignored.
+    assert_eq!(filled((0.0, 0.0), 0.5).get_pixel(9, 0).data, [9 * 6, 0, 0, 255]);
+
+    // Zoomed in, the crop stays inside the image even with the focus on its
+    // corner.
+    let image = precise::from_rgba(&RgbaImage::from_fn(40, 10, |x, _| if x < 30 {
+        Rgba { data: [255, 0, 0, 255] }
+    } else {
+        Rgba { data: [0, 0, 255, 255] }
+    }));
+    let filled = |focus, zoom| precise::quantize(
+        &fill(&image, 10, 10, focus, zoom, Resampling::default()), Dither::None);
+    let zoomed = filled((1.0, 1.0), 2.0);
+    assert!(zoomed.pixels().all(|pixel| pixel.data == [0, 0, 255, 255]));
+    // No further than a single pixel.
+    let zoomed = filled((0.0, 0.0), 1000.0);
+    assert!(zoomed.pixels().all(|pixel| pixel.data == [255, 0, 0, 255]));
+}
+
+#[test]
+fn test_target_size() {
+    let options = ModeOptions::default();
+    let size = |mode: &str, width, height| {
+        target_size(mode.parse().unwrap(), width, height, 1920, 1080, &options)
+    };
+    // A 4:3 image on a 16:9 output.
+    assert_eq!(size("fill", 800.0, 600.0), (1920, 1440));
+    assert_eq!(size("fit", 800.0, 600.0), (1440, 1080));
+    assert_eq!(size("stretch", 800.0, 600.0), (1920, 1080));
+    assert_eq!(size("center", 800.0, 600.0), (800, 600));
+    assert_eq!(size("tile", 800.0, 600.0), (800, 600));
+    // A portrait image, and sizes that don't divide evenly.
+    assert_eq!(size("fill", 1000.0, 3000.0), (1920, 5760));
+    assert_eq!(size("fit", 333.3, 1000.0), (360, 1080));
+    // Tiny documents still rasterize to at least a pixel.
+    assert_eq!(size("center", 0.2, 0.2), (1, 1));
+
+    let zoomed = ModeOptions { zoom: 2.0, ..ModeOptions::default() };
+    assert_eq!(target_size(BackgroundMode::Fill, 800.0, 600.0, 1920, 1080, &zoomed),
+               (3840, 2880));
+    let mut scaled = ModeOptions::default();
+    scaled.tile.scale = 0.5;
+    assert_eq!(target_size(BackgroundMode::Tile, 800.0, 600.0, 1920, 1080, &scaled), (400, 300));
+}
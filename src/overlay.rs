@@ -0,0 +1,346 @@
+//! Layers drawn over the finished background: lines of text such as the
+//! hostname, the IP address and a clock.
+//!
+//! Text is set in the bundled DejaVu Sans, so it looks the same on every
+//! machine, and rendered through `svg`.
+
+use std::ffi::CStr;
+use std::net::Ipv4Addr;
+use std::ptr;
+use std::rc::Rc;
+use std::str::FromStr;
+
+use chrono::{DateTime, Duration, Local, Timelike};
+use chrono::format::{Item, StrftimeItems};
+use image::{Rgba, RgbaImage};
+use libc;
+use resvg::usvg;
+
+use svg::Svg;
+
+const FONT: &'static [u8] = include_bytes!("../assets/DejaVuSans.ttf");
+const FONT_FAMILY: &'static str = "DejaVu Sans";
+
+/// How an overlay is aligned along one axis.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    Start,
+    Center,
+    End,
+}
+
+/// Where on the output an overlay goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Anchor {
+    pub x: Align,
+    pub y: Align,
+}
+
+impl FromStr for Anchor {
+    type Err = String;
+
+    /// Parses `top-left`, `top`, `top-right`, `left`, `center`, `right`,
+    /// `bottom-left`, `bottom` or `bottom-right`.
+    fn from_str(s: &str) -> Result<Anchor, String> {
+        let (y, x) = match s {
+            "top-left"     => (Align::Start, Align::Start),
+            "top"          => (Align::Start, Align::Center),
+            "top-right"    => (Align::Start, Align::End),
+            "left"         => (Align::Center, Align::Start),
+            "center"       => (Align::Center, Align::Center),
+            "right"        => (Align::Center, Align::End),
+            "bottom-left"  => (Align::End, Align::Start),
+            "bottom"       => (Align::End, Align::Center),
+            "bottom-right" => (Align::End, Align::End),
+            _              => return Err(format!("Invalid position: {}", s)),
+        };
+        Ok(Anchor { x: x, y: y })
+    }
+}
+
+impl Anchor {
+    /// The top left corner of something `width` x `height` placed on an
+    /// output `scr_width` x `scr_height`, `margin` pixels from its edges.
+    pub fn place(&self, width: f64, height: f64,
+                 scr_width: f64, scr_height: f64, margin: f64) -> (f64, f64) {
+        let along = |align: Align, size: f64, scr_size: f64| match align {
+            Align::Start => margin,
+            Align::Center => (scr_size - size) / 2.0,
+            Align::End => scr_size - size - margin,
+        };
+        (along(self.x, width, scr_width), along(self.y, height, scr_height))
+    }
+}
+
+/// A part of a text template.
+#[derive(Debug, Clone, PartialEq)]
+enum Field {
+    Literal(String),
+    Hostname,
+    /// The first IPv4 address of an interface that is up, other than the
+    /// loopback and bridges to containers and VMs.
+    Ip,
+    User,
+    /// The local time, formatted like `strftime`.
+    Time(String),
+}
+
+/// A line of text with fields in braces, e.g. `{hostname} {time:%H:%M}`.
+/// `{{` and `}}` stand for braces.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    fields: Vec<Field>,
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Template, String> {
+        let mut fields = Vec::new();
+        let mut literal = String::new();
+        let mut rest = s;
+        while let Some(c) = rest.chars().next() {
+            if rest.starts_with("{{") || rest.starts_with("}}") {
+                literal.push(c);
+                rest = &rest[2..];
+                continue
+            }
+            if c != '{' {
+                literal.push(c);
+                rest = &rest[c.len_utf8()..];
+                continue
+            }
+            let end = rest.find('}')
+                .ok_or_else(|| format!("Unclosed field in text: {}", s))?;
+            let field = match &rest[1..end] {
+                "hostname" => Field::Hostname,
+                "ip" => Field::Ip,
+                "user" => Field::User,
+                "time" => Field::Time("%H:%M".into()),
+                name if name.starts_with("time:") => {
+                    let format = &name["time:".len()..];
+                    if StrftimeItems::new(format).any(|item| item == Item::Error) {
+                        return Err(format!("Invalid time format: {}", format))
+                    }
+                    Field::Time(format.into())
+                },
+                name => return Err(format!("Unknown field in text: {{{}}}", name)),
+            };
+            if !literal.is_empty() {
+                fields.push(Field::Literal(literal.split_off(0)));
+            }
+            fields.push(field);
+            rest = &rest[end + 1..];
+        }
+        if !literal.is_empty() {
+            fields.push(Field::Literal(literal));
+        }
+        Ok(Template { fields: fields })
+    }
+}
+
+impl Template {
+    /// Fills in the fields.
+    pub fn expand(&self, now: DateTime<Local>) -> String {
+        self.fields.iter().map(|field| match *field {
+            Field::Literal(ref text) => text.clone(),
+            Field::Hostname => hostname(),
+            Field::Ip => ip_address(),
+            Field::User => ::std::env::var("USER").unwrap_or_default(),
+            Field::Time(ref format) => now.format(format).to_string(),
+        }).collect()
+    }
+
+    /// How often the text changes: every second, every minute, or never.
+    fn period(&self) -> Option<Duration> {
+        self.fields.iter().filter_map(|field| match *field {
+            Field::Time(ref format) => Some(if shows_seconds(format) {
+                Duration::seconds(1)
+            } else {
+                Duration::minutes(1)
+            }),
+            _ => None,
+        }).min()
+    }
+}
+
+/// Whether a time format changes more often than once a minute.
+fn shows_seconds(format: &str) -> bool {
+    ["%S", "%T", "%X", "%r", "%c", "%s", "%f", "%.f", "%+"].iter()
+        .any(|specifier| format.contains(specifier))
+}
+
+/// A rectangle of an output: left, top, width and height.
+pub type Area = (u32, u32, u32, u32);
+
+/// Lines of text over the background.
+#[derive(Debug, Clone)]
+pub struct TextOverlay {
+    pub lines: Vec<Template>,
+    pub anchor: Anchor,
+    /// Font size in pixels, the text is also kept this far from the edges.
+    pub size: f64,
+    pub color: Rgba<u8>,
+    /// Parsing options with the font loaded, shared by every redraw.
+    options: Rc<usvg::Options<'static>>,
+}
+
+impl TextOverlay {
+    pub fn new(lines: Vec<Template>) -> TextOverlay {
+        let mut options = usvg::Options::default();
+        options.fontdb_mut().load_font_data(FONT.to_vec());
+        options.font_family = FONT_FAMILY.into();
+        TextOverlay {
+            lines: lines,
+            anchor: "bottom-right".parse().unwrap(),
+            size: 24.0,
+            color: Rgba { data: [255, 255, 255, 255] },
+            options: Rc::new(options),
+        }
+    }
+
+    /// The text at the time `now` on a transparent image the size of the
+    /// output.
+    pub fn layer(&self, width: u32, height: u32, now: DateTime<Local>) -> RgbaImage {
+        let document = self.document(width, height, now);
+        match Svg::parse_with(document.as_bytes(), &self.options) {
+            Ok(svg) => svg.rasterize(width, height),
+            Err(err) => {
+                eprintln!("Could not draw the text: {}", err);
+                RgbaImage::new(width, height)
+            }
+        }
+    }
+
+    /// The next time after `now` the text changes, if it shows the time.
+    pub fn next_update(&self, now: DateTime<Local>) -> Option<DateTime<Local>> {
+        let period = self.lines.iter().filter_map(Template::period).min()?;
+        let start = now.with_nanosecond(0).unwrap_or(now);
+        let start = if period >= Duration::minutes(1) {
+            start.with_second(0).unwrap_or(start)
+        } else {
+            start
+        };
+        Some(start + period)
+    }
+
+    /// An SVG document the size of the output with the text on it.
+    fn document(&self, width: u32, height: u32, now: DateTime<Local>) -> String {
+        let line_height = self.size * 1.2;
+        let (_, top) = self.anchor.place(0.0, line_height * self.lines.len() as f64,
+                                         width as f64, height as f64, self.size);
+        let (x, text_anchor) = match self.anchor.x {
+            Align::Start => (self.size, "start"),
+            Align::Center => (width as f64 / 2.0, "middle"),
+            Align::End => (width as f64 - self.size, "end"),
+        };
+        let color = self.color;
+        let mut document = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\">\
+             <text font-family=\"{}\" font-size=\"{}\" text-anchor=\"{}\" \
+             fill=\"#{:02x}{:02x}{:02x}\" fill-opacity=\"{}\" xml:space=\"preserve\">",
+            width, height, FONT_FAMILY, self.size, text_anchor,
+            color[0], color[1], color[2], color[3] as f64 / 255.0);
+        for (i, line) in self.lines.iter().enumerate() {
+            // The baseline sits about an ascent below the top of the line.
+            let baseline = top + line_height * i as f64 + self.size * 0.95;
+            document.push_str(&format!("<tspan x=\"{}\" y=\"{}\">{}</tspan>",
+                                       x, baseline, escape(&line.expand(now))));
+        }
+        document.push_str("</text></svg>");
+        document
+    }
+}
+
+/// The smallest area holding every pixel of `layer` that isn't fully
+/// transparent, if there is one.
+pub fn covered(layer: &RgbaImage) -> Option<Area> {
+    let mut bounds: Option<(u32, u32, u32, u32)> = None;
+    for (x, y, pixel) in layer.enumerate_pixels() {
+        if pixel[3] == 0 {
+            continue
+        }
+        bounds = Some(match bounds {
+            Some((left, top, right, bottom)) =>
+                (left.min(x), top.min(y), right.max(x), bottom.max(y)),
+            None => (x, y, x, y),
+        });
+    }
+    bounds.map(|(left, top, right, bottom)| (left, top, right - left + 1, bottom - top + 1))
+}
+
+/// The smallest area holding both areas.
+pub fn union(a: Option<Area>, b: Option<Area>) -> Option<Area> {
+    match (a, b) {
+        (Some((ax, ay, aw, ah)), Some((bx, by, bw, bh))) => {
+            let (left, top) = (ax.min(bx), ay.min(by));
+            let (right, bottom) = ((ax + aw).max(bx + bw), (ay + ah).max(by + bh));
+            Some((left, top, right - left, bottom - top))
+        },
+        (area, None) | (None, area) => area,
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    let result = unsafe {
+        libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len())
+    };
+    if result != 0 {
+        return String::new()
+    }
+    let end = buf.iter().position(|&byte| byte == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..end]).into_owned()
+}
+
+fn ip_address() -> String {
+    let mut addresses: *mut libc::ifaddrs = ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut addresses) } != 0 {
+        return String::new()
+    }
+    let mut found = None;
+    let mut current = addresses;
+    while !current.is_null() {
+        let interface = unsafe { &*current };
+        let flags = interface.ifa_flags;
+        let up = flags & libc::IFF_UP as u32 != 0 && flags & libc::IFF_LOOPBACK as u32 == 0;
+        if up && !interface.ifa_addr.is_null()
+            && unsafe { (*interface.ifa_addr).sa_family } as libc::c_int == libc::AF_INET {
+            let address = unsafe { &*(interface.ifa_addr as *const libc::sockaddr_in) };
+            let name = unsafe { CStr::from_ptr(interface.ifa_name) }.to_string_lossy();
+            if !name.starts_with("docker") && !name.starts_with("virbr") {
+                found = Some(Ipv4Addr::from(u32::from_be(address.sin_addr.s_addr)));
+                break
+            }
+        }
+        current = interface.ifa_next;
+    }
+    unsafe { libc::freeifaddrs(addresses) };
+    found.map(|address| address.to_string()).unwrap_or_default()
+}
+
+#[test]
+fn test_templates() {
+    use chrono::TimeZone;
+
+    let now = Local.from_local_datetime(
+        &::chrono::NaiveDate::from_ymd_opt(2017, 6, 21).unwrap().and_hms_opt(9, 5, 30).unwrap())
+        .unwrap();
+    let template: Template = "{{up}} {time} {time:%d.%m.}".parse().unwrap();
+    assert_eq!(template.expand(now), "{up} 09:05 21.06.");
+    assert!("{time:%Q}".parse::<Template>().is_err());
+    assert!("{uptime}".parse::<Template>().is_err());
+    assert!("{hostname".parse::<Template>().is_err());
+
+    let clock = TextOverlay::new(vec![template]);
+    assert_eq!(clock.next_update(now), Some(now + Duration::seconds(30)));
+    let seconds = TextOverlay::new(vec!["{time:%T}".parse().unwrap()]);
+    assert_eq!(seconds.next_update(now), Some(now + Duration::seconds(1)));
+    let host = TextOverlay::new(vec!["{hostname} <{ip}>".parse().unwrap()]);
+    assert_eq!(host.next_update(now), None);
+    assert!(host.document(100, 100, now).contains("&lt;"));
+}
@@ -0,0 +1,146 @@
+//! Colors from the palettes of the desktop theme, so the background can
+//! follow it:
+//!
+//! - `wal:NAME` looks up `background`, `foreground`, `cursor` or
+//!   `color0` to `color15` in the palette pywal generated last, from
+//!   `~/.cache/wal/colors.json`.
+//! - `xrdb:NAME` looks up an X resource, e.g. `xrdb:background` or
+//!   `xrdb:URxvt.color4`, in `xrdb -query` or else in `~/.Xresources`.
+//!
+//! Palettes are read whenever a color is parsed, i.e. at startup and when
+//! the options are reloaded.
+
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Resolves a palette reference to the color it names, in whatever
+/// syntax the palette uses. Returns `None` if `color` is no reference.
+pub fn resolve(color: &str) -> Option<Result<String, String>> {
+    if color.starts_with("wal:") {
+        Some(wal(&color["wal:".len()..]))
+    } else if color.starts_with("xrdb:") {
+        Some(xrdb(&color["xrdb:".len()..]))
+    } else {
+        None
+    }
+}
+
+fn home() -> PathBuf {
+    env::var_os("HOME").map(PathBuf::from).unwrap_or_default()
+}
+
+fn read_file(path: &PathBuf) -> Result<String, String> {
+    let mut contents = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut contents))
+        .map_err(|err| format!("Could not read {}: {}", path.display(), err))?;
+    Ok(contents)
+}
+
+fn wal(name: &str) -> Result<String, String> {
+    let cache = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| home().join(".cache"));
+    wal_in(&cache, name)
+}
+
+/// Looks up a color in the pywal palette kept in the `cache` directory.
+fn wal_in(cache: &Path, name: &str) -> Result<String, String> {
+    let path = cache.join("wal").join("colors.json");
+    let colors = read_file(&path)?;
+    wal_color(&colors, name)
+        .ok_or_else(|| format!("No color {} in the pywal palette {}", name, path.display()))
+}
+
+/// Finds `"NAME": "VALUE"` in pywal's colors.json. The names are unique
+/// across its sections, so the nesting doesn't matter.
+fn wal_color(json: &str, name: &str) -> Option<String> {
+    let key = format!("\"{}\"", name);
+    let rest = &json[json.find(&key)? + key.len()..];
+    let rest = rest.trim_start().trim_start_matches(':').trim_start();
+    if !rest.starts_with('"') {
+        return None
+    }
+    let value = &rest[1..];
+    Some(value[..value.find('"')?].to_string())
+}
+
+fn xrdb(name: &str) -> Result<String, String> {
+    // xrdb needs an X server, e.g. Xwayland, and runs the file through
+    // the C preprocessor. Without one the file is read as it is.
+    let queried = Command::new("xrdb").arg("-query").output().ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).into_owned());
+    let resources = match queried {
+        Some(resources) => resources,
+        None => read_file(&home().join(".Xresources"))?,
+    };
+    xresource(&resources, name)
+        .ok_or_else(|| format!("No X resource {}", name))
+}
+
+/// Looks up a resource in `NAME: VALUE` lines. An exact match wins over
+/// wildcards such as `*.NAME` or `*NAME`, later lines win over earlier
+/// ones. Simple `#define NAME VALUE` lines are substituted.
+fn xresource(resources: &str, name: &str) -> Option<String> {
+    let mut defines: Vec<(&str, &str)> = Vec::new();
+    let (mut exact, mut wildcard) = (None, None);
+    for line in resources.lines().map(str::trim) {
+        if line.starts_with("#define") {
+            let mut parts = line["#define".len()..].split_whitespace();
+            if let (Some(macro_name), Some(value)) = (parts.next(), parts.next()) {
+                defines.push((macro_name, value));
+            }
+            continue
+        }
+        if line.starts_with('!') || line.starts_with('#') {
+            continue
+        }
+        let colon = match line.find(':') {
+            Some(colon) => colon,
+            None => continue,
+        };
+        let (resource, value) = (line[..colon].trim(), line[colon + 1..].trim());
+        let value = defines.iter().rev()
+            .find(|&&(macro_name, _)| macro_name == value)
+            .map_or(value, |&(_, defined)| defined);
+        if resource == name {
+            exact = Some(value);
+        } else if resource.starts_with('*')
+            && resource.trim_start_matches(|c| c == '*' || c == '.') == name {
+            wildcard = Some(value);
+        }
+    }
+    exact.or(wildcard).map(str::to_string)
+}
+
+#[test]
+fn test_palettes() {
+    let json = r##"{
+        "wallpaper": "/home/me/wall.jpg",
+        "special": { "background": "#0b0d10", "foreground": "#c4c5c6" },
+        "colors": { "color0": "#0b0d10", "color4": "#5f7f9b" }
+    }"##;
+    assert_eq!(wal_color(json, "color4"), Some("#5f7f9b".to_string()));
+    assert_eq!(wal_color(json, "background"), Some("#0b0d10".to_string()));
+    assert_eq!(wal_color(json, "color9"), None);
+
+    let cache = env::temp_dir().join(format!("wc-bg-test-{}", ::std::process::id()));
+    ::std::fs::create_dir_all(cache.join("wal")).unwrap();
+    ::std::fs::write(cache.join("wal").join("colors.json"), json).unwrap();
+    let found = (wal_in(&cache, "color4"), wal_in(&cache, "color9"));
+    ::std::fs::remove_dir_all(&cache).unwrap();
+    assert_eq!(found.0, Ok("#5f7f9b".to_string()));
+    assert!(found.1.is_err());
+
+    let resources = "! comment\n#define bg #1d1f21\n*.background: bg\n\
+                     *foreground:\t#c5c8c6\nURxvt.background: #000000\n*color4: #81a2be\n";
+    assert_eq!(xresource(resources, "background"), Some("#1d1f21".to_string()));
+    assert_eq!(xresource(resources, "foreground"), Some("#c5c8c6".to_string()));
+    assert_eq!(xresource(resources, "URxvt.background"), Some("#000000".to_string()));
+    assert_eq!(xresource(resources, "color4"), Some("#81a2be".to_string()));
+    assert_eq!(xresource(resources, "color5"), None);
+}
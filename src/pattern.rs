@@ -0,0 +1,245 @@
+//! Procedural backgrounds, rendered at the output's resolution so a
+//! background needs no image at all.
+//!
+//! Noise and plasma run through the colors like a gradient does, the
+//! checkerboard, stripes and dots take turns between them.
+
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+use image::{ImageBuffer, Rgba};
+
+use backdrop::Gradient;
+use color;
+use precise::PreciseImage;
+
+/// The colors patterns use unless others are given.
+const DEFAULT_COLORS: &'static str = "#1a1a1a,#333333";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// Perlin noise, several octaves of it, like clouds.
+    Noise,
+
+    /// Interfering sine waves, the demoscene plasma.
+    Plasma,
+
+    Checkerboard,
+
+    Stripes,
+
+    /// A grid of dots over the first color.
+    Dots,
+}
+
+impl FromStr for Kind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Kind, String> {
+        match s {
+            "noise"        => Ok(Kind::Noise),
+            "plasma"       => Ok(Kind::Plasma),
+            "checkerboard" => Ok(Kind::Checkerboard),
+            "stripes"      => Ok(Kind::Stripes),
+            "dots"         => Ok(Kind::Dots),
+            _              => Err(format!("Invalid pattern: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    kind: Kind,
+    colors: Vec<Rgba<u8>>,
+    /// The size of the squares, stripes and the space between dots, or of
+    /// the features of noise and plasma, in pixels.
+    pub size: f64,
+    /// Rotation of the pattern in degrees, clockwise.
+    pub angle: f64,
+    /// Varies noise and plasma, the same seed renders the same pattern.
+    pub seed: u32,
+}
+
+impl FromStr for Pattern {
+    type Err = String;
+
+    /// Parses `KIND[:COLOR,COLOR...]`, e.g. `stripes:#222,#2a2a2a`.
+    fn from_str(s: &str) -> Result<Pattern, String> {
+        let mut parts = s.splitn(2, ':');
+        let kind = parts.next().unwrap_or("").parse()?;
+        let colors = color::split_list(parts.next().unwrap_or(DEFAULT_COLORS)).into_iter()
+            .map(color::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        if colors.len() < 2 {
+            return Err(format!("A pattern needs at least two colors: {}", s))
+        }
+        Ok(Pattern { kind: kind, colors: colors, size: 64.0, angle: 0.0, seed: 0 })
+    }
+}
+
+impl Pattern {
+    /// Renders the pattern at the given resolution.
+    pub fn render(&self, width: u32, height: u32) -> PreciseImage {
+        let (sin, cos) = self.angle.to_radians().sin_cos();
+        let (cx, cy) = (width as f64 / 2.0, height as f64 / 2.0);
+        let size = self.size.max(1.0);
+        // Pattern coordinates, in units of `size` from the center.
+        let point = |x: f64, y: f64| {
+            let (x, y) = (x - cx, y - cy);
+            ((x * cos + y * sin) / size, (y * cos - x * sin) / size)
+        };
+        let gradient = Gradient::new(self.colors.clone());
+        let permutation = permutation(self.seed);
+        ImageBuffer::from_fn(width, height, |x, y| {
+            let color = match self.kind {
+                Kind::Noise => {
+                    let (u, v) = point(x as f64 + 0.5, y as f64 + 0.5);
+                    gradient.color_at(0.5 + fractal_noise(&permutation, u, v))
+                },
+                Kind::Plasma => {
+                    let (u, v) = point(x as f64 + 0.5, y as f64 + 0.5);
+                    gradient.color_at(plasma(self.seed, u, v))
+                },
+                // The sharp edges are smoothed by averaging several samples
+                // per pixel.
+                _ => {
+                    const SAMPLES: u32 = 3;
+                    let mut sum = [0.0; 4];
+                    for sy in 0..SAMPLES {
+                        for sx in 0..SAMPLES {
+                            let (u, v) = point(x as f64 + (sx as f64 + 0.5) / SAMPLES as f64,
+                                               y as f64 + (sy as f64 + 0.5) / SAMPLES as f64);
+                            let color = self.colors[self.index_at(u, v)];
+                            for c in 0..4 {
+                                sum[c] += color[c] as f64;
+                            }
+                        }
+                    }
+                    let n = (SAMPLES * SAMPLES) as f64;
+                    [sum[0] / n, sum[1] / n, sum[2] / n, sum[3] / n]
+                },
+            };
+            Rgba { data: [color[0] as f32, color[1] as f32, color[2] as f32, color[3] as f32] }
+        })
+    }
+
+    /// Which color the checkerboard, stripes or dots have at a point.
+    fn index_at(&self, u: f64, v: f64) -> usize {
+        let count = self.colors.len() as i64;
+        let (column, row) = (u.floor() as i64, v.floor() as i64);
+        let index = match self.kind {
+            Kind::Checkerboard => column + row,
+            Kind::Stripes => column,
+            _ => {
+                let (dx, dy) = (u - column as f64 - 0.5, v - row as f64 - 0.5);
+                if dx * dx + dy * dy > 0.3 * 0.3 {
+                    return 0
+                }
+                // The dots take turns between the other colors.
+                return 1 + (column + row).rem_euclid(count - 1) as usize
+            },
+        };
+        index.rem_euclid(count) as usize
+    }
+}
+
+/// A shuffle of 0 to 255 determined by the seed, twice in a row so lookups
+/// can add to an entry without wrapping.
+fn permutation(seed: u32) -> Vec<u8> {
+    let mut table: Vec<u8> = (0..256).map(|i| i as u8).collect();
+    // xorshift, a zero state would stay zero.
+    let mut state = (seed ^ 0x9E37_79B9).max(1);
+    for i in (1..256).rev() {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        table.swap(i, state as usize % (i + 1));
+    }
+    let copy = table.clone();
+    table.extend(copy);
+    table
+}
+
+/// Classic Perlin noise, between -1 and 1.
+fn noise(permutation: &[u8], x: f64, y: f64) -> f64 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let (ix, iy) = ((x0 as i64 & 255) as usize, (y0 as i64 & 255) as usize);
+    let fade = |t: f64| t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+    let gradient = |hash: u8, x: f64, y: f64| match hash & 7 {
+        0 => x + y,
+        1 => x - y,
+        2 => -x + y,
+        3 => -x - y,
+        4 => x,
+        5 => -x,
+        6 => y,
+        _ => -y,
+    };
+    let corner = |dx: usize, dy: usize| {
+        let hash = permutation[permutation[ix + dx] as usize + iy + dy];
+        gradient(hash, fx - dx as f64, fy - dy as f64)
+    };
+    let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+    let (u, v) = (fade(fx), fade(fy));
+    lerp(lerp(corner(0, 0), corner(1, 0), u),
+         lerp(corner(0, 1), corner(1, 1), u),
+         v)
+}
+
+/// Four octaves of noise, each twice as fine and half as strong. Mostly
+/// between -0.5 and 0.5, the peaks beyond are clamped by the gradient.
+fn fractal_noise(permutation: &[u8], x: f64, y: f64) -> f64 {
+    let (mut sum, mut amplitude, mut frequency) = (0.0, 0.5, 1.0);
+    for _ in 0..4 {
+        sum += noise(permutation, x * frequency, y * frequency) * amplitude;
+        amplitude /= 2.0;
+        frequency *= 2.0;
+    }
+    sum / 0.9375 * 0.7
+}
+
+/// Plasma between 0 and 1, where the seed moves the center of the rings
+/// and shifts the waves.
+fn plasma(seed: u32, x: f64, y: f64) -> f64 {
+    let phase = (seed % 360) as f64 * PI / 180.0;
+    let (cx, cy) = (2.0 * phase.cos(), 2.0 * phase.sin());
+    let value = (x + phase).sin()
+        + ((y - phase) / 1.3).sin()
+        + ((x + y) / 1.7).sin()
+        + (((x - cx).powi(2) + (y - cy).powi(2)).sqrt() * 1.2).sin();
+    0.5 + 0.5 * (value * PI / 4.0).sin()
+}
+
+#[test]
+fn test_patterns() {
+    use dither::Dither;
+    use precise;
+
+    let mut checkerboard: Pattern = "checkerboard:#000,#fff".parse().unwrap();
+    checkerboard.size = 4.0;
+    let image = precise::quantize(&checkerboard.render(16, 16), Dither::None);
+    // The center of the output is a corner between squares.
+    assert_eq!(image.get_pixel(9, 9).data, [0, 0, 0, 255]);
+    assert_eq!(image.get_pixel(5, 9).data, [255, 255, 255, 255]);
+    assert_eq!(image.get_pixel(5, 5).data, [0, 0, 0, 255]);
+
+    let mut stripes: Pattern = "stripes:#f00,#0f0,#00f".parse().unwrap();
+    stripes.size = 2.0;
+    let image = precise::quantize(&stripes.render(12, 1), Dither::None);
+    let reds: Vec<u8> = (0..12).map(|x| image.get_pixel(x, 0)[0]).collect();
+    assert_eq!(reds, vec![255, 255, 0, 0, 0, 0, 255, 255, 0, 0, 0, 0]);
+
+    let dots: Pattern = "dots".parse().unwrap();
+    let image = precise::quantize(&dots.render(64, 64), Dither::None);
+    assert_eq!(image.get_pixel(0, 0).data, [0x33, 0x33, 0x33, 255]);
+    assert_eq!(image.get_pixel(32, 0).data, [0x1a, 0x1a, 0x1a, 255]);
+
+    let mut noise: Pattern = "noise:#000,#fff".parse().unwrap();
+    let first = precise::quantize(&noise.render(32, 32), Dither::None).into_vec();
+    assert_eq!(first, precise::quantize(&noise.render(32, 32), Dither::None).into_vec());
+    noise.seed = 1;
+    assert!(first != precise::quantize(&noise.render(32, 32), Dither::None).into_vec());
+    assert!("noise:#000".parse::<Pattern>().is_err());
+    assert!("waves".parse::<Pattern>().is_err());
+}
@@ -0,0 +1,160 @@
+//! Decoding of the Netpbm formats: PBM, PGM and PPM, both in their plain
+//! (ASCII) and raw (binary) variants.
+
+use image::{ImageBuffer, RgbaImage};
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    /// Skips whitespace and `#` comments, which run to the end of the line.
+    fn skip_whitespace(&mut self) {
+        while let Some(&byte) = self.data.get(self.position) {
+            if byte == b'#' {
+                while self.data.get(self.position).map_or(false, |&b| b != b'\n') {
+                    self.position += 1;
+                }
+            } else if byte.is_ascii_whitespace() {
+                self.position += 1;
+            } else {
+                break
+            }
+        }
+    }
+
+    /// Reads a decimal number, preceded by whitespace or comments.
+    fn number(&mut self) -> Result<u32, String> {
+        self.skip_whitespace();
+        let start = self.position;
+        while self.data.get(self.position).map_or(false, |b| b.is_ascii_digit()) {
+            self.position += 1;
+        }
+        String::from_utf8_lossy(&self.data[start..self.position]).parse()
+            .map_err(|_| "Invalid number in PNM image".to_string())
+    }
+
+    /// Reads a single `0` or `1` of a plain PBM, which need not be separated.
+    fn bit(&mut self) -> Result<bool, String> {
+        self.skip_whitespace();
+        let bit = match self.data.get(self.position) {
+            Some(&b'0') => false,
+            Some(&b'1') => true,
+            _ => return Err("Invalid pixel in PBM image".into()),
+        };
+        self.position += 1;
+        Ok(bit)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        let byte = *self.data.get(self.position).ok_or("PNM image is truncated")?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    /// Reads a binary sample, two bytes big endian if `max_value` needs them.
+    fn sample(&mut self, max_value: u32) -> Result<u32, String> {
+        if max_value < 256 {
+            Ok(self.byte()? as u32)
+        } else {
+            Ok((self.byte()? as u32) << 8 | self.byte()? as u32)
+        }
+    }
+}
+
+/// Scales a sample from `0..=max_value` to `0..=255`.
+fn scale(value: u32, max_value: u32) -> Result<u8, String> {
+    if value > max_value {
+        return Err("PNM sample is larger than the maximum value".into())
+    }
+    Ok(((value * 255 + max_value / 2) / max_value) as u8)
+}
+
+/// Decodes a PBM, PGM or PPM image.
+pub fn decode(data: &[u8]) -> Result<RgbaImage, String> {
+    let kind = match (data.get(0), data.get(1)) {
+        (Some(&b'P'), Some(&kind)) if kind >= b'1' && kind <= b'6' => kind,
+        (Some(&b'P'), Some(&b'7')) => return Err("PAM images are not supported".into()),
+        _ => return Err("Not a PNM image".into()),
+    };
+    let mut reader = Reader { data: data, position: 2 };
+    let width = reader.number()?;
+    let height = reader.number()?;
+    let max_value = match kind {
+        b'1' | b'4' => 1,
+        _ => reader.number()?,
+    };
+    if width == 0 || height == 0 || max_value == 0 || max_value > 65535 {
+        return Err("Invalid PNM header".into())
+    }
+    // Even packed bitmaps need a bit for every pixel.
+    if width as usize * height as usize / 8 > data.len() {
+        return Err("PNM image is truncated".into())
+    }
+    let binary = kind >= b'4';
+    if binary {
+        // A single whitespace byte separates the header from the samples.
+        reader.position += 1;
+    }
+    let channels = match kind {
+        b'3' | b'6' => 3,
+        _ => 1,
+    };
+
+    let mut pixels = Vec::with_capacity(width as usize * height as usize * 4);
+    for _ in 0..height {
+        let mut bits = 0u8;
+        for x in 0..width {
+            let value = match kind {
+                // In PBM images 1 is black.
+                b'1' => if reader.bit()? { 0 } else { 255 },
+                b'4' => {
+                    if x % 8 == 0 {
+                        bits = reader.byte()?;
+                    }
+                    let bit = bits & (0x80 >> (x % 8)) != 0;
+                    if bit { 0 } else { 255 }
+                },
+                _ => {
+                    let mut rgb = [0u8; 3];
+                    for c in 0..channels {
+                        let sample = if binary {
+                            reader.sample(max_value)?
+                        } else {
+                            reader.number()?
+                        };
+                        rgb[c] = scale(sample, max_value)?;
+                    }
+                    if channels == 1 {
+                        rgb = [rgb[0]; 3];
+                    }
+                    pixels.extend_from_slice(&rgb);
+                    pixels.push(255);
+                    continue
+                },
+            };
+            pixels.extend_from_slice(&[value, value, value, 255]);
+        }
+    }
+    ImageBuffer::from_raw(width, height, pixels)
+        .ok_or_else(|| "Invalid PNM image".to_string())
+}
+
+#[test]
+fn test_pnm() {
+    use image::Rgba;
+
+    let plain = decode(b"P1\n# a comment\n2 2\n01\n1 0\n").unwrap();
+    assert_eq!(plain.get_pixel(0, 0), &Rgba { data: [255, 255, 255, 255] });
+    assert_eq!(plain.get_pixel(1, 0), &Rgba { data: [0, 0, 0, 255] });
+    assert_eq!(plain.get_pixel(0, 1), &Rgba { data: [0, 0, 0, 255] });
+
+    let raw = decode(b"P6 1 1 65535\n\xff\xff\x80\x00\x00\x00").unwrap();
+    assert_eq!(raw.get_pixel(0, 0), &Rgba { data: [255, 128, 0, 255] });
+
+    let gray = decode(b"P2 2 1 4 0 4").unwrap();
+    assert_eq!(gray.get_pixel(1, 0), &Rgba { data: [255, 255, 255, 255] });
+
+    assert!(decode(b"P5 2 2 255\n\x00").is_err());
+}
@@ -0,0 +1,140 @@
+//! The shared memory a background is drawn into, kept for as long as the
+//! size and format of its buffers stay the same.
+//!
+//! Every background has two buffers in one pool, so a frame can be written
+//! into one while the compositor still reads the other. The compositor
+//! tells when it is done reading a buffer with a `wl_buffer.release` event.
+
+use std::io::{Seek, SeekFrom, Write};
+use std::os::unix::io::AsRawFd;
+
+use tempfile::NamedTempFile;
+use wayland_client::{EventQueue, EventQueueHandle};
+use wayland_client::protocol::{wl_buffer, wl_shm, wl_shm_pool};
+use wayland_client::protocol::wl_shm::Format as WlShmFormat;
+
+/// How many buffers a pool starts with.
+const BUFFERS: usize = 2;
+
+/// Handles the release events of one buffer.
+pub struct ReleaseHandler {
+    /// Whether the compositor doesn't read the buffer, so it can be
+    /// written.
+    pub released: bool,
+}
+
+impl wl_buffer::Handler for ReleaseHandler {
+    fn release(&mut self,
+               _evqh: &mut EventQueueHandle,
+               _proxy: &wl_buffer::WlBuffer) {
+        self.released = true;
+    }
+}
+
+declare_handler!(ReleaseHandler, wl_buffer::Handler, wl_buffer::WlBuffer);
+
+/// The layout of the buffers in a pool.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Layout {
+    pub width: u32,
+    pub height: u32,
+    /// Bytes per row.
+    pub stride: u32,
+    pub format: WlShmFormat,
+}
+
+impl Layout {
+    fn size(&self) -> usize {
+        self.stride as usize * self.height as usize
+    }
+}
+
+/// The buffers of one background.
+pub struct BufferPool {
+    file: NamedTempFile,
+    pool: wl_shm_pool::WlShmPool,
+    layout: Layout,
+    /// Each buffer with the id of its `ReleaseHandler`.
+    buffers: Vec<(wl_buffer::WlBuffer, usize)>,
+    /// The buffer written last, the next one is tried first.
+    last: usize,
+}
+
+impl BufferPool {
+    pub fn new(shm: &wl_shm::WlShm,
+               event_queue: &mut EventQueue,
+               layout: Layout) -> BufferPool {
+        let file = NamedTempFile::new().expect("Unable to create a tempfile.");
+        let size = layout.size() * BUFFERS;
+        file.set_len(size as u64).expect("Could not truncate length of file");
+        let pool = shm.create_pool(file.as_raw_fd(), size as i32);
+        let mut pool = BufferPool {
+            file: file,
+            pool: pool,
+            layout: layout,
+            buffers: Vec::new(),
+            last: 0,
+        };
+        for _ in 0..BUFFERS {
+            pool.add_buffer(event_queue);
+        }
+        pool
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// Writes a frame into a buffer the compositor doesn't read and returns
+    /// that buffer. Compositors that hold on to every buffer get another.
+    pub fn write(&mut self,
+                 pixels: &[u8],
+                 event_queue: &mut EventQueue) -> &wl_buffer::WlBuffer {
+        let count = self.buffers.len();
+        let free = (1..count + 1).map(|i| (self.last + i) % count).find(|&index| {
+            let (_, release_id) = self.buffers[index];
+            event_queue.state().get_handler::<ReleaseHandler>(release_id).released
+        });
+        let index = match free {
+            Some(index) => index,
+            None => {
+                let size = self.layout.size() * (count + 1);
+                self.file.set_len(size as u64).expect("Could not grow the buffer file");
+                self.pool.resize(size as i32);
+                self.add_buffer(event_queue);
+                count
+            }
+        };
+        let (ref buffer, release_id) = self.buffers[index];
+        event_queue.state().get_mut_handler::<ReleaseHandler>(release_id).released = false;
+        let offset = (index * self.layout.size()) as u64;
+        self.file.seek(SeekFrom::Start(offset))
+            .and_then(|_| self.file.write_all(pixels))
+            .expect("Could not write to the buffer file");
+        self.last = index;
+        buffer
+    }
+
+    /// Destroys the buffers and the pool, the memory is freed once the
+    /// compositor no longer shows them.
+    pub fn destroy(self) {
+        for (buffer, _) in self.buffers {
+            buffer.destroy();
+        }
+        self.pool.destroy();
+    }
+
+    fn add_buffer(&mut self, event_queue: &mut EventQueue) {
+        let layout = self.layout;
+        let offset = self.buffers.len() * layout.size();
+        let buffer = self.pool.create_buffer(offset as i32,
+                                             layout.width as i32,
+                                             layout.height as i32,
+                                             layout.stride as i32,
+                                             layout.format)
+            .expect("Could not create buffer");
+        let release_id = event_queue.add_handler(ReleaseHandler { released: true });
+        event_queue.register::<_, ReleaseHandler>(&buffer, release_id);
+        self.buffers.push((buffer, release_id));
+    }
+}
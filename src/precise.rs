@@ -0,0 +1,67 @@
+//! Images with a float per channel, which backgrounds are rendered in.
+//!
+//! Scaling, filters and compositing each create colors between the 8 bit
+//! steps. Rounding after every one of them would bring back the banding
+//! that dithering hides, so the channels stay floats between 0 and 255,
+//! with straight alpha, until the finished background is dithered down to
+//! 8 bits once (see `render::finish`).
+
+use image::{ImageBuffer, Rgba, RgbaImage};
+
+use dither::Dither;
+
+pub type PreciseImage = ImageBuffer<Rgba<f32>, Vec<f32>>;
+
+/// Widens an 8 bit image, which loses nothing.
+pub fn from_rgba(image: &RgbaImage) -> PreciseImage {
+    ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
+        let pixel = image.get_pixel(x, y);
+        Rgba { data: [pixel[0] as f32, pixel[1] as f32, pixel[2] as f32, pixel[3] as f32] }
+    })
+}
+
+/// Multiplies the colors by the alpha, which filters and resizing need so
+/// the arbitrary colors of transparent pixels don't bleed into their
+/// neighbors.
+pub fn premultiply(mut image: PreciseImage) -> PreciseImage {
+    for pixel in image.pixels_mut() {
+        let alpha = pixel[3] / 255.0;
+        for c in 0..3 {
+            pixel[c] *= alpha;
+        }
+    }
+    image
+}
+
+/// Undoes `premultiply`. Colors brighter than the alpha allows, e.g. from
+/// ringing filters, are clamped.
+pub fn unpremultiply(mut image: PreciseImage) -> PreciseImage {
+    for pixel in image.pixels_mut() {
+        let alpha = pixel[3];
+        for c in 0..3 {
+            pixel[c] = if alpha > 0.0 { (pixel[c] * 255.0 / alpha).min(255.0) } else { 0.0 };
+        }
+    }
+    image
+}
+
+/// Rounds an image to 8 bits, the colors with `dither`.
+pub fn quantize(image: &PreciseImage, dither: Dither) -> RgbaImage {
+    ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
+        quantize_pixel(image.get_pixel(x, y), x, y, dither)
+    })
+}
+
+/// Rounds the pixel at `x`, `y` to 8 bits, the colors with `dither`.
+pub fn quantize_pixel(pixel: &Rgba<f32>, x: u32, y: u32, dither: Dither) -> Rgba<u8> {
+    Rgba { data: [dither.quantize(pixel[0], x, y),
+                  dither.quantize(pixel[1], x, y),
+                  dither.quantize(pixel[2], x, y),
+                  pixel[3].round().max(0.0).min(255.0) as u8] }
+}
+
+#[test]
+fn test_round_trip() {
+    let image = RgbaImage::from_fn(4, 4, |x, y| Rgba { data: [x as u8 * 60, y as u8, 255, 128] });
+    assert_eq!(quantize(&from_rgba(&image), Dither::Ordered).into_vec(), image.into_vec());
+}
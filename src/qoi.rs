@@ -0,0 +1,90 @@
+//! Decoding of QOI, the "Quite OK Image" format.
+
+use image::{ImageBuffer, RgbaImage};
+
+const HEADER_SIZE: usize = 14;
+
+const OP_RGB: u8 = 0b1111_1110;
+const OP_RGBA: u8 = 0b1111_1111;
+const OP_INDEX: u8 = 0b0000_0000;
+const OP_DIFF: u8 = 0b0100_0000;
+const OP_LUMA: u8 = 0b1000_0000;
+const OP_RUN: u8 = 0b1100_0000;
+const MASK: u8 = 0b1100_0000;
+
+fn read_u32(data: &[u8]) -> u32 {
+    (data[0] as u32) << 24 | (data[1] as u32) << 16 | (data[2] as u32) << 8 | data[3] as u32
+}
+
+fn hash(pixel: [u8; 4]) -> usize {
+    (pixel[0] as usize * 3 + pixel[1] as usize * 5 + pixel[2] as usize * 7
+     + pixel[3] as usize * 11) % 64
+}
+
+/// Decodes a QOI image. Images with three channels decode as opaque.
+pub fn decode(data: &[u8]) -> Result<RgbaImage, String> {
+    if data.len() < HEADER_SIZE || &data[..4] != b"qoif" {
+        return Err("Not a QOI image".into())
+    }
+    let width = read_u32(&data[4..]);
+    let height = read_u32(&data[8..]);
+    let pixel_count = width as usize * height as usize;
+    // Every pixel needs at least a bit of data, runs aside.
+    if width == 0 || height == 0 || pixel_count / 62 > data.len() {
+        return Err("Invalid QOI header".into())
+    }
+
+    let mut pixels = Vec::with_capacity(pixel_count * 4);
+    let mut index = [[0u8; 4]; 64];
+    let mut pixel = [0u8, 0, 0, 255];
+    let mut bytes = data[HEADER_SIZE..].iter().cloned();
+    let mut next = || bytes.next().ok_or_else(|| "QOI image is truncated".to_string());
+    let mut decoded = 0;
+    while decoded < pixel_count {
+        let op = next()?;
+        let mut run = 1;
+        match op {
+            OP_RGB => {
+                pixel[0] = next()?;
+                pixel[1] = next()?;
+                pixel[2] = next()?;
+            },
+            OP_RGBA => {
+                pixel = [next()?, next()?, next()?, next()?];
+            },
+            _ => match op & MASK {
+                OP_INDEX => pixel = index[op as usize],
+                OP_DIFF => {
+                    pixel[0] = pixel[0].wrapping_add((op >> 4 & 0x03).wrapping_sub(2));
+                    pixel[1] = pixel[1].wrapping_add((op >> 2 & 0x03).wrapping_sub(2));
+                    pixel[2] = pixel[2].wrapping_add((op & 0x03).wrapping_sub(2));
+                },
+                OP_LUMA => {
+                    let second = next()?;
+                    let green = (op & 0x3f).wrapping_sub(32);
+                    pixel[0] = pixel[0].wrapping_add(green.wrapping_add(second >> 4).wrapping_sub(8));
+                    pixel[1] = pixel[1].wrapping_add(green);
+                    pixel[2] = pixel[2].wrapping_add(green.wrapping_add(second & 0x0f).wrapping_sub(8));
+                },
+                _ => run = (op & 0x3f) as usize + 1,
+            },
+        }
+        index[hash(pixel)] = pixel;
+        for _ in 0..run.min(pixel_count - decoded) {
+            pixels.extend_from_slice(&pixel);
+        }
+        decoded += run;
+    }
+    ImageBuffer::from_raw(width, height, pixels)
+        .ok_or_else(|| "Invalid QOI image".to_string())
+}
+
+#[test]
+fn test_qoi() {
+    let mut data = b"qoif\x00\x00\x00\x04\x00\x00\x00\x01\x04\x00".to_vec();
+    // A red pixel, the same again, a slightly darker one, back to red by index.
+    data.extend_from_slice(&[OP_RGB, 200, 0, 0, OP_RUN, OP_DIFF | 0b01_10_10, OP_INDEX | hash([200, 0, 0, 255]) as u8]);
+    data.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+    let image = decode(&data).unwrap().into_vec();
+    assert_eq!(image, vec![200, 0, 0, 255, 200, 0, 0, 255, 199, 0, 0, 255, 200, 0, 0, 255]);
+}
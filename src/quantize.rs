@@ -0,0 +1,235 @@
+//! Reducing an image to a few representative colors, for the `palette`
+//! subcommand and the `dominant` padding.
+
+use std::str::FromStr;
+
+use image::{Rgba, RgbaImage};
+
+/// How the colors are picked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    /// Splits the colors into boxes at the median of their widest channel,
+    /// until there are enough boxes.
+    MedianCut,
+
+    /// Refines the median cut colors with k-means clustering, which fits
+    /// them closer to the image but takes longer.
+    KMeans,
+}
+
+impl FromStr for Method {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Method, String> {
+        match s {
+            "median-cut" => Ok(Method::MedianCut),
+            "k-means"    => Ok(Method::KMeans),
+            _            => Err(format!("Invalid quantization method: {}", s)),
+        }
+    }
+}
+
+/// How a palette is printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Output {
+    /// `[{"color": "#rrggbb", "population": 0.42}, ...]`
+    Json,
+
+    /// `color0='#rrggbb'` lines, for `eval` or `source`.
+    Shell,
+}
+
+impl FromStr for Output {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Output, String> {
+        match s {
+            "json"  => Ok(Output::Json),
+            "shell" => Ok(Output::Shell),
+            _       => Err(format!("Invalid palette format: {}", s)),
+        }
+    }
+}
+
+/// A color of the palette.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Swatch {
+    pub color: Rgba<u8>,
+    /// The fraction of the image's pixels closest to this color.
+    pub population: f64,
+}
+
+/// At most this many pixels are looked at, evenly spread over the image.
+const SAMPLES: u32 = 65536;
+
+/// Picks up to `count` colors representing the opaque parts of the image,
+/// the most common first. Mostly transparent pixels are left out, so a
+/// fully transparent image has no palette.
+pub fn palette(image: &RgbaImage, count: usize, method: Method) -> Vec<Swatch> {
+    let (width, height) = image.dimensions();
+    let step = ((width as f64 * height as f64 / SAMPLES as f64).sqrt().floor() as u32).max(1);
+    let mut pixels = Vec::new();
+    for y in (0..height).step_by(step as usize) {
+        for x in (0..width).step_by(step as usize) {
+            let pixel = image.get_pixel(x, y);
+            if pixel[3] >= 128 {
+                pixels.push([pixel[0], pixel[1], pixel[2]]);
+            }
+        }
+    }
+    if pixels.is_empty() || count == 0 {
+        return Vec::new()
+    }
+    let total = pixels.len() as f64;
+    let clusters = median_cut(pixels, count);
+    let clusters = match method {
+        Method::MedianCut => clusters,
+        Method::KMeans => k_means(clusters),
+    };
+    let mut swatches: Vec<Swatch> = clusters.iter()
+        .filter(|cluster| !cluster.is_empty())
+        .map(|cluster| {
+            let mean = mean(cluster);
+            Swatch {
+                color: Rgba { data: [mean[0].round() as u8,
+                                     mean[1].round() as u8,
+                                     mean[2].round() as u8,
+                                     255] },
+                population: cluster.len() as f64 / total,
+            }
+        })
+        .collect();
+    swatches.sort_by(|a, b| b.population.partial_cmp(&a.population).unwrap());
+    swatches
+}
+
+/// The most common color of the image, if it has opaque parts.
+pub fn dominant(image: &RgbaImage) -> Option<Rgba<u8>> {
+    palette(image, 5, Method::KMeans).first().map(|swatch| swatch.color)
+}
+
+/// Prints a palette.
+pub fn format(swatches: &[Swatch], output: Output) -> String {
+    let hex = |color: Rgba<u8>| format!("#{:02x}{:02x}{:02x}", color[0], color[1], color[2]);
+    match output {
+        Output::Json => {
+            let entries: Vec<String> = swatches.iter()
+                .map(|swatch| format!("  {{\"color\": \"{}\", \"population\": {:.4}}}",
+                                      hex(swatch.color), swatch.population))
+                .collect();
+            format!("[\n{}\n]\n", entries.join(",\n"))
+        },
+        Output::Shell => swatches.iter().enumerate()
+            .map(|(i, swatch)| format!("color{}='{}'\n", i, hex(swatch.color)))
+            .collect(),
+    }
+}
+
+fn mean(pixels: &[[u8; 3]]) -> [f64; 3] {
+    let mut sum = [0.0; 3];
+    for pixel in pixels {
+        for c in 0..3 {
+            sum[c] += pixel[c] as f64;
+        }
+    }
+    let n = pixels.len().max(1) as f64;
+    [sum[0] / n, sum[1] / n, sum[2] / n]
+}
+
+/// The widest channel of the pixels, and how wide it is.
+fn widest_channel(pixels: &[[u8; 3]]) -> (usize, u8) {
+    (0..3)
+        .map(|c| {
+            let min = pixels.iter().map(|pixel| pixel[c]).min().unwrap_or(0);
+            let max = pixels.iter().map(|pixel| pixel[c]).max().unwrap_or(0);
+            (c, max - min)
+        })
+        .max_by_key(|&(_, range)| range)
+        .unwrap()
+}
+
+/// Splits the pixels into up to `count` boxes. The box split next is the
+/// one with the most pixels times its widest range, so large areas of
+/// similar colors get several entries before a few outliers get one.
+fn median_cut(pixels: Vec<[u8; 3]>, count: usize) -> Vec<Vec<[u8; 3]>> {
+    let mut boxes = vec![pixels];
+    while boxes.len() < count {
+        let next = boxes.iter().enumerate()
+            .map(|(i, pixels)| (i, pixels.len() as u64 * widest_channel(pixels).1 as u64))
+            .filter(|&(_, score)| score > 0)
+            .max_by_key(|&(_, score)| score);
+        let index = match next {
+            Some((index, _)) => index,
+            None => break,
+        };
+        let mut pixels = boxes.swap_remove(index);
+        let (channel, _) = widest_channel(&pixels);
+        pixels.sort_by_key(|pixel| pixel[channel]);
+        let upper = pixels.split_off(pixels.len() / 2);
+        boxes.push(pixels);
+        boxes.push(upper);
+    }
+    boxes
+}
+
+/// Moves every pixel to the cluster with the closest mean until that no
+/// longer changes anything, starting from `clusters`.
+fn k_means(clusters: Vec<Vec<[u8; 3]>>) -> Vec<Vec<[u8; 3]>> {
+    const ITERATIONS: usize = 16;
+    let mut centers: Vec<[f64; 3]> = clusters.iter().map(|cluster| mean(cluster)).collect();
+    let mut clusters = clusters;
+    for _ in 0..ITERATIONS {
+        let mut next: Vec<Vec<[u8; 3]>> = vec![Vec::new(); centers.len()];
+        let mut moved = false;
+        for (from, cluster) in clusters.iter().enumerate() {
+            for pixel in cluster {
+                let distance = |center: &[f64; 3]| (0..3)
+                    .map(|c| (pixel[c] as f64 - center[c]).powi(2))
+                    .sum::<f64>();
+                let closest = (0..centers.len())
+                    .min_by(|&a, &b| distance(&centers[a]).partial_cmp(&distance(&centers[b])).unwrap())
+                    .unwrap();
+                moved |= closest != from;
+                next[closest].push(*pixel);
+            }
+        }
+        clusters = next;
+        if !moved {
+            break
+        }
+        for (center, cluster) in centers.iter_mut().zip(&clusters) {
+            if !cluster.is_empty() {
+                *center = mean(cluster);
+            }
+        }
+    }
+    clusters
+}
+
+#[test]
+fn test_palette() {
+    // Three quarters navy with some noise, one quarter orange.
+    let image = RgbaImage::from_fn(64, 64, |x, y| if x < 48 {
+        Rgba { data: [10 + (y % 3) as u8, 20, 80 + (x % 5) as u8, 255] }
+    } else {
+        Rgba { data: [250, 140, 0, 255] }
+    });
+    let navy = |color: Rgba<u8>| color.data.iter().zip(&[11, 20, 82, 255])
+        .all(|(&a, &b)| (a as i32 - b).abs() <= 2);
+    let swatches = palette(&image, 2, Method::KMeans);
+    assert_eq!(swatches.len(), 2);
+    assert!((swatches[0].population - 0.75).abs() < 1e-9);
+    assert_eq!(swatches[0].color, Rgba { data: [11, 20, 82, 255] });
+    assert_eq!(swatches[1].color, Rgba { data: [250, 140, 0, 255] });
+    // Median cut splits the navy half into two boxes first.
+    let swatches = palette(&image, 3, Method::MedianCut);
+    assert_eq!(swatches.len(), 3);
+    assert!((swatches[0].population - 0.5).abs() < 1e-9);
+    assert!(navy(swatches[0].color));
+    assert!(swatches.contains(&Swatch { color: Rgba { data: [250, 140, 0, 255] }, population: 0.25 }));
+    assert!(navy(dominant(&image).unwrap()));
+    assert_eq!(dominant(&RgbaImage::new(4, 4)), None);
+    let swatches = palette(&image, 2, Method::KMeans);
+    assert_eq!(format(&swatches, Output::Shell), "color0='#0b1452'\ncolor1='#fa8c00'\n");
+    assert!(format(&swatches, Output::Json).contains("{\"color\": \"#fa8c00\", \"population\": 0.2500}"));
+}
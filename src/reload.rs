@@ -0,0 +1,64 @@
+//! Reloading the options on `SIGUSR1`, e.g. after a theme changed the
+//! palette the colors come from.
+//!
+//! The signal handler only writes to a pipe, which the main loop polls
+//! along with the Wayland display.
+
+use std::io;
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicI32, Ordering};
+
+use libc;
+
+/// The end of the pipe the signal handler writes to.
+static WRITE_FD: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn on_signal(_signal: libc::c_int) {
+    let fd = WRITE_FD.load(Ordering::Relaxed);
+    if fd >= 0 {
+        // If the pipe is full a reload is pending anyway.
+        unsafe { libc::write(fd, b"r".as_ptr() as *const libc::c_void, 1) };
+    }
+}
+
+/// Tells when a reload was requested.
+pub struct Reload {
+    read_fd: RawFd,
+}
+
+impl Reload {
+    /// Starts listening for `SIGUSR1`.
+    pub fn listen() -> io::Result<Reload> {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC | libc::O_NONBLOCK) } < 0 {
+            return Err(io::Error::last_os_error())
+        }
+        WRITE_FD.store(fds[1], Ordering::Relaxed);
+        unsafe {
+            let mut action: libc::sigaction = ::std::mem::zeroed();
+            action.sa_sigaction = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            if libc::sigaction(libc::SIGUSR1, &action, ::std::ptr::null_mut()) < 0 {
+                return Err(io::Error::last_os_error())
+            }
+        }
+        Ok(Reload { read_fd: fds[0] })
+    }
+
+    /// The file descriptor that becomes readable when a reload is requested.
+    pub fn fd(&self) -> RawFd {
+        self.read_fd
+    }
+
+    /// Whether a reload was requested since the last call. Signals that
+    /// arrived in the meantime are handled by one reload.
+    pub fn requested(&self) -> bool {
+        let mut buf = [0u8; 64];
+        let mut requested = false;
+        while unsafe { libc::read(self.read_fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) } > 0 {
+            requested = true;
+        }
+        requested
+    }
+}
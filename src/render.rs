@@ -0,0 +1,299 @@
+//! Rendering what a background shows into an image the size of an output.
+
+use std::rc::Rc;
+use std::str::FromStr;
+
+use chrono::{DateTime, Local};
+use image::{load_from_memory, ImageError, Rgba, RgbaImage};
+
+use way_cooler_client_helpers::wayland::Resolution;
+
+use backdrop::{self, Backdrop};
+use color;
+use dither::Dither;
+use filter::{self, Filter};
+use hdr::ToneMapping;
+use icc::OutputProfiles;
+use load;
+use mode::{self, BackgroundMode, ModeOptions};
+use overlay::{self, Area, TextOverlay};
+use precise::{self, PreciseImage};
+use resample::Resampling;
+use svg::{self, Svg};
+use transform::{OutputTransforms, Transform};
+use watermark::OutputWatermarks;
+
+/// What a background shows.
+#[derive(Debug, Clone)]
+pub enum Content {
+    /// The official Way Cooler background.
+    Official,
+
+    /// The image at a path.
+    Image(String),
+
+    /// An image read once ahead of time, e.g. from standard input.
+    Loaded {
+        name: String,
+        image: Rc<PreciseImage>,
+    },
+
+    /// A color or gradient without an image.
+    Backdrop(Backdrop),
+}
+
+impl Content {
+    /// The title of the background's shell surface.
+    pub fn title(&self) -> String {
+        match *self {
+            Content::Official => "Official background".into(),
+            Content::Image(ref path) |
+            Content::Loaded { name: ref path, .. } => format!("Background Image: {}", path),
+            Content::Backdrop(Backdrop::Color(color)) =>
+                format!("Background Color: {:02x}{:02x}{:02x}", color[0], color[1], color[2]),
+            Content::Backdrop(Backdrop::Gradient(_)) => "Background Gradient".into(),
+            Content::Backdrop(Backdrop::Pattern(_)) => "Background Pattern".into(),
+        }
+    }
+}
+
+impl FromStr for Content {
+    type Err = String;
+
+    /// Parses `color:COLOR`, `gradient:COLOR,COLOR...`,
+    /// `pattern:KIND[:COLOR,COLOR...]`, `official` or an image path.
+    fn from_str(s: &str) -> Result<Content, String> {
+        if s.starts_with("color:") {
+            color::parse(&s["color:".len()..])
+                .map(|color| Content::Backdrop(Backdrop::Color(color)))
+        } else if s.starts_with("gradient:") {
+            s["gradient:".len()..].parse()
+                .map(|gradient| Content::Backdrop(Backdrop::Gradient(gradient)))
+        } else if s.starts_with("pattern:") {
+            s["pattern:".len()..].parse()
+                .map(|pattern| Content::Backdrop(Backdrop::Pattern(pattern)))
+        } else if s == "official" {
+            Ok(Content::Official)
+        } else {
+            Ok(Content::Image(s.into()))
+        }
+    }
+}
+
+/// How images are turned into backgrounds.
+#[derive(Debug, Clone)]
+pub struct RenderOptions {
+    pub mode: BackgroundMode,
+    pub mode_options: ModeOptions,
+    pub transforms: OutputTransforms,
+    pub filters: Vec<Filter>,
+    /// How images with more than 8 bits per channel are shown.
+    pub tone_mapping: ToneMapping,
+    /// How the finished background is rounded to 8 bits.
+    pub dither: Dither,
+    /// Whether the finished background is also kept before it is rounded,
+    /// for 10 bit buffers.
+    pub deep: bool,
+    /// Whether images are scaled in linear light.
+    pub linear_scaling: bool,
+    /// The color profiles of the outputs, backgrounds are rendered in sRGB
+    /// and converted to them.
+    pub profiles: OutputProfiles,
+    /// What transparent parts of images, and the area around them left by
+    /// the mode, are filled with. `None` leaves them transparent.
+    pub backdrop: Option<Backdrop>,
+    /// Images drawn over the background, per output.
+    pub watermarks: OutputWatermarks,
+    /// Text drawn over the background, and over the watermark.
+    pub text: Option<TextOverlay>,
+}
+
+/// A background finished for an output, with what it takes to redraw the
+/// text over it.
+#[derive(Debug, Clone)]
+pub struct Finished {
+    pub image: RgbaImage,
+    /// The image before it was rounded, only kept if `deep` is set.
+    pub precise: Option<PreciseImage>,
+    /// The background under the text, only kept if there is text.
+    under_text: Option<PreciseImage>,
+    /// Where the text was drawn.
+    text_area: Option<Area>,
+}
+
+/// Draws the overlays over a background from `render_base`, converts it to
+/// the output's profile and rounds it to 8 bits.
+pub fn finish(base: PreciseImage,
+              options: &RenderOptions,
+              index: usize,
+              resolution: Resolution) -> Finished {
+    finish_at(base, options, index, resolution, Local::now())
+}
+
+fn finish_at(base: PreciseImage,
+             options: &RenderOptions,
+             index: usize,
+             resolution: Resolution,
+             now: DateTime<Local>) -> Finished {
+    let image = match options.watermarks.for_output(index, resolution) {
+        Some(watermark) => watermark.draw(base, Resampling { linear: options.linear_scaling }),
+        None => base
+    };
+    let (image, under_text, text_area) = match options.text {
+        Some(ref text) => {
+            let layer = text.layer(image.width(), image.height(), now);
+            let mut with_text = image.clone();
+            backdrop::composite(&mut with_text, &precise::from_rgba(&layer));
+            (with_text, Some(image), overlay::covered(&layer))
+        },
+        None => (image, None, None)
+    };
+    let image = options.profiles.apply(image, index, resolution);
+    Finished {
+        image: precise::quantize(&image, options.dither),
+        precise: if options.deep { Some(image) } else { None },
+        under_text: under_text,
+        text_area: text_area,
+    }
+}
+
+impl Finished {
+    /// Redraws the text at the time `now`. Only the area the text covered
+    /// before or covers now is composited, converted and rounded again.
+    pub fn redraw_text(&mut self,
+                       options: &RenderOptions,
+                       index: usize,
+                       resolution: Resolution,
+                       now: DateTime<Local>) {
+        let (under_text, text) = match (self.under_text.as_ref(), options.text.as_ref()) {
+            (Some(under_text), Some(text)) => (under_text, text),
+            _ => return,
+        };
+        let layer = text.layer(under_text.width(), under_text.height(), now);
+        let text_area = overlay::covered(&layer);
+        let conversion = options.profiles.for_output(index, resolution);
+        if let Some((left, top, width, height)) = overlay::union(self.text_area, text_area) {
+            for y in top..top + height {
+                for x in left..left + width {
+                    let pixel = layer.get_pixel(x, y);
+                    let pixel = Rgba { data: [pixel[0] as f32, pixel[1] as f32,
+                                              pixel[2] as f32, pixel[3] as f32] };
+                    let mut pixel = backdrop::over(&pixel, under_text.get_pixel(x, y));
+                    if let Some(ref conversion) = conversion {
+                        conversion.convert(&mut pixel);
+                    }
+                    self.image.put_pixel(x, y, precise::quantize_pixel(&pixel, x, y, options.dither));
+                    if let Some(ref mut precise) = self.precise {
+                        precise.put_pixel(x, y, pixel);
+                    }
+                }
+            }
+        }
+        self.text_area = text_area;
+    }
+}
+
+/// Renders the content for the `index`-th output in sRGB, without the
+/// overlays.
+pub fn render_base(content: &Content,
+                   options: &RenderOptions,
+                   index: usize,
+                   resolution: Resolution) -> PreciseImage {
+    let (scr_width, scr_height) = (resolution.w as u32, resolution.h as u32);
+    let transform = options.transforms.for_output(index, resolution);
+    let mut mode_options = options.mode_options;
+    let image = match *content {
+        Content::Backdrop(ref backdrop) =>
+            return backdrop.render(scr_width, scr_height),
+        // Images that can't be read fall back to the official background
+        Content::Image(ref path) => {
+            let image = load::read(path).map_err(ImageError::from).and_then(|data| {
+                if !svg::is_svg(&data) {
+                    return load::decode(&data, path, &options.tone_mapping)
+                }
+                let svg = Svg::parse(&data).map_err(ImageError::FormatError)?;
+                // Already rendered at the tile size
+                mode_options.tile.scale = 1.0;
+                Ok(rasterize(&svg, options.mode, transform, scr_width, scr_height,
+                             &options.mode_options))
+            });
+            image.map_err(|err| eprintln!("Could not read {}: {}", path, err)).ok()
+        },
+        Content::Loaded { ref image, .. } => Some((**image).clone()),
+        Content::Official => None,
+    };
+
+    let image = image
+        .unwrap_or_else(|| {
+            let image = load_from_memory(include_bytes!("../assets/official-background.png"))
+                .expect("Could not read in official background image");
+            precise::from_rgba(&image.to_rgba())
+        });
+    let image = transform.apply(image);
+
+    let resampling = Resampling { linear: options.linear_scaling };
+    let image = mode::apply(options.mode, image, scr_width, scr_height, &mode_options, resampling);
+
+    let image = filter::apply_all(&options.filters, image);
+
+    match options.backdrop {
+        Some(ref backdrop) => {
+            let mut canvas = backdrop.render(scr_width, scr_height);
+            backdrop::composite(&mut canvas, &image);
+            canvas
+        },
+        None => image
+    }
+}
+
+/// Rasterizes a vector image at the size the mode shows it at on the output,
+/// in its orientation before the `transform` is applied.
+fn rasterize(svg: &Svg,
+             mode: BackgroundMode,
+             transform: Transform,
+             scr_width: u32,
+             scr_height: u32,
+             mode_options: &ModeOptions) -> PreciseImage {
+    let (width, height) = svg.size();
+    let swap = transform.swaps_dimensions();
+    let (width, height) = if swap { (height, width) } else { (width, height) };
+    let (width, height) = mode::target_size(mode, width, height, scr_width, scr_height, mode_options);
+    let (width, height) = if swap { (height, width) } else { (width, height) };
+    precise::from_rgba(&svg.rasterize(width, height))
+}
+
+#[test]
+fn test_redraw_text() {
+    use chrono::TimeZone;
+
+    let mut text = TextOverlay::new(vec!["{time:%-S}".parse().unwrap()]);
+    text.size = 12.0;
+    let options = RenderOptions {
+        mode: BackgroundMode::Fill,
+        mode_options: ModeOptions::default(),
+        transforms: OutputTransforms::default(),
+        filters: Vec::new(),
+        tone_mapping: ToneMapping::default(),
+        dither: Dither::Ordered,
+        deep: true,
+        linear_scaling: false,
+        profiles: OutputProfiles::default(),
+        backdrop: None,
+        watermarks: OutputWatermarks::default(),
+        text: Some(text),
+    };
+    let resolution = Resolution { w: 120, h: 40 };
+    let gradient = Backdrop::Gradient("#202020,#808080".parse().unwrap());
+    let at = |second| Local.with_ymd_and_hms(2017, 6, 21, 12, 0, second).unwrap();
+
+    // From "10" to the narrower "9", so some of the old text is left to
+    // clear.
+    let mut finished = finish_at(gradient.render(120, 40), &options, 0, resolution, at(10));
+    let before = finished.image.clone();
+    finished.redraw_text(&options, 0, resolution, at(9));
+    let after = finish_at(gradient.render(120, 40), &options, 0, resolution, at(9));
+    assert!(before.into_vec() != after.image.clone().into_vec());
+    assert_eq!(finished.image.into_vec(), after.image.into_vec());
+    assert!(finished.precise.unwrap().into_raw() == after.precise.unwrap().into_raw());
+    assert!(finished.text_area.is_some() && finished.text_area == after.text_area);
+}
@@ -0,0 +1,374 @@
+//! Time-of-day wallpapers, read from the file given with `--schedule`.
+//!
+//! Every line gives a time of day and what to show from then on, optionally
+//! followed by `fade=SECONDS`, how long the transition into it takes. It
+//! replaces `--transition-duration` whichever `--transition` is used, a
+//! wipe or slide as well as a fade. Times are either
+//! `HH:MM` or `sunrise`/`sunset` with an optional `+H:MM` or `-H:MM` offset.
+//! What to show is an image path, `color:COLOR`, `gradient:COLOR,...`,
+//! `pattern:KIND[:COLOR,...]` or `official`:
+//!
+//! ```text
+//! # Needed for sunrise and sunset, in degrees north and east.
+//! location 52.52 13.40
+//!
+//! 07:00         /home/me/Pictures/morning.jpg
+//! sunrise+1:00  /home/me/Pictures/day.jpg      fade=60
+//! sunset-0:30   /home/me/Pictures/evening.jpg  fade=300
+//! 22:00         color:1a1a2e
+//! ```
+//!
+//! Sunrise and sunset are computed offline from the location. On days the
+//! sun does not rise or set there, entries using them are skipped. Times
+//! that don't exist on a day, because the clocks go forward, start when
+//! they would have without the change.
+//!
+//! Times are in the time zone of the `now` they are computed from, i.e.
+//! `Local` outside of tests.
+
+use std::fs::File;
+use std::io::Read;
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::{DateTime, Duration as ChronoDuration, LocalResult, NaiveDate, NaiveDateTime, TimeZone};
+#[cfg(test)]
+use chrono::FixedOffset;
+
+use render::Content;
+use sun;
+
+/// How many days to look around today for the surrounding entries, in case
+/// some days have none (e.g. only sunset entries during polar day).
+const SEARCH_DAYS: i64 = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TimeOfDay {
+    /// Minutes after midnight.
+    Fixed(i64),
+    /// Minutes after sunrise, may be negative.
+    Sunrise(i64),
+    /// Minutes after sunset, may be negative.
+    Sunset(i64),
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    time: TimeOfDay,
+    pub content: Content,
+    /// How long the transition from the previous entry takes, whatever its
+    /// kind, if it differs from `--transition-duration`.
+    pub fade: Option<Duration>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    /// Latitude and longitude, in degrees north and east.
+    location: Option<(f64, f64)>,
+    entries: Vec<Entry>,
+}
+
+/// Parses `H:MM` into minutes.
+fn parse_hours_minutes(s: &str) -> Option<i64> {
+    let mut parts = s.splitn(2, ':');
+    let hours = parts.next()?.parse::<i64>().ok()?;
+    let minutes = parts.next()?.parse::<i64>().ok()?;
+    if hours < 0 || minutes < 0 || minutes >= 60 {
+        return None
+    }
+    Some(hours * 60 + minutes)
+}
+
+fn parse_time(s: &str) -> Result<TimeOfDay, String> {
+    let invalid = || format!("Invalid time, expected HH:MM, sunrise or sunset: {}", s);
+    for &(name, constructor) in &[("sunrise", TimeOfDay::Sunrise as fn(i64) -> TimeOfDay),
+                                  ("sunset", TimeOfDay::Sunset as fn(i64) -> TimeOfDay)] {
+        if !s.starts_with(name) {
+            continue
+        }
+        let offset = &s[name.len()..];
+        let minutes = if offset.is_empty() {
+            0
+        } else if offset.starts_with('+') {
+            parse_hours_minutes(&offset[1..]).ok_or_else(invalid)?
+        } else if offset.starts_with('-') {
+            -parse_hours_minutes(&offset[1..]).ok_or_else(invalid)?
+        } else {
+            return Err(invalid())
+        };
+        return Ok(constructor(minutes))
+    }
+    match parse_hours_minutes(s) {
+        Some(minutes) if minutes < 24 * 60 => Ok(TimeOfDay::Fixed(minutes)),
+        _ => Err(invalid()),
+    }
+}
+
+/// The first time it is `time` in `zone`, if it is at all. Times repeated
+/// when the clocks go back are ambiguous, `LocalResult::earliest` doesn't
+/// always pick the earlier one.
+fn first_local<Tz: TimeZone>(zone: &Tz, time: NaiveDateTime) -> Option<DateTime<Tz>> {
+    match zone.from_local_datetime(&time) {
+        LocalResult::Single(time) => Some(time),
+        LocalResult::Ambiguous(first, second) => Some(first.min(second)),
+        LocalResult::None => None,
+    }
+}
+
+impl Schedule {
+    /// Reads the schedule from a file.
+    pub fn read(path: &str) -> Result<Schedule, String> {
+        let mut contents = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut contents))
+            .map_err(|err| format!("Could not read schedule {}: {}", path, err))?;
+        contents.parse()
+    }
+
+    /// When the entry at `index` starts on `date` in `zone`, if it does.
+    fn start_on<Tz: TimeZone>(&self, index: usize, date: NaiveDate,
+                              zone: &Tz) -> Option<DateTime<Tz>> {
+        let midnight = date.and_hms_opt(0, 0, 0)?;
+        let sun = |pick: fn((i64, i64)) -> i64, offset: i64| {
+            let (latitude, longitude) = self.location?;
+            let noon = zone.from_local_datetime(&date.and_hms_opt(12, 0, 0)?).earliest()?;
+            let time = pick(sun::sunrise_sunset(noon.timestamp(), latitude, longitude)?);
+            zone.timestamp_opt(time + offset * 60, 0).single()
+        };
+        match self.entries[index].time {
+            TimeOfDay::Fixed(minutes) => {
+                let time = midnight + ChronoDuration::minutes(minutes);
+                // Times skipped when the clocks go forward start an hour
+                // later.
+                first_local(zone, time).or_else(|| first_local(zone, time + ChronoDuration::hours(1)))
+            },
+            TimeOfDay::Sunrise(offset) => sun(|(sunrise, _)| sunrise, offset),
+            TimeOfDay::Sunset(offset) => sun(|(_, sunset)| sunset, offset),
+        }
+    }
+
+    /// Every entry start between `SEARCH_DAYS` before and after `now`,
+    /// sorted by time.
+    fn starts_around<Tz: TimeZone>(&self, now: &DateTime<Tz>) -> Vec<(DateTime<Tz>, usize)> {
+        let (today, zone) = (now.date_naive(), now.timezone());
+        let mut starts = Vec::new();
+        for day in -SEARCH_DAYS..SEARCH_DAYS + 1 {
+            let date = today + ChronoDuration::days(day);
+            for index in 0..self.entries.len() {
+                if let Some(start) = self.start_on(index, date, &zone) {
+                    starts.push((start, index));
+                }
+            }
+        }
+        starts.sort_by(|a, b| a.0.cmp(&b.0));
+        starts
+    }
+
+    /// The entry that should be shown at `now`.
+    pub fn current<Tz: TimeZone>(&self, now: DateTime<Tz>) -> &Entry {
+        let index = self.starts_around(&now).into_iter()
+            .filter(|start| start.0 <= now)
+            .last()
+            .map(|(_, index)| index)
+            .unwrap_or(0);
+        &self.entries[index]
+    }
+
+    /// When the next entry starts after `now`.
+    pub fn next_change<Tz: TimeZone>(&self, now: DateTime<Tz>) -> DateTime<Tz> {
+        self.starts_around(&now).into_iter()
+            .map(|(start, _)| start)
+            .find(|start| *start > now)
+            // Nothing starts soon, check again tomorrow.
+            .unwrap_or(now + ChronoDuration::days(1))
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Schedule, String> {
+        let mut location = None;
+        let mut entries = Vec::new();
+        for line in s.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue
+            }
+            let mut words: Vec<&str> = line.split_whitespace().collect();
+            if words[0] == "location" {
+                let coordinates = words[1..].iter()
+                    .map(|c| c.parse::<f64>().ok())
+                    .collect::<Option<Vec<_>>>();
+                location = match coordinates {
+                    Some(ref c) if c.len() == 2 => Some((c[0], c[1])),
+                    _ => return Err(format!("Invalid location, expected LATITUDE LONGITUDE: {}", line)),
+                };
+                continue
+            }
+            let time = parse_time(words[0])?;
+            let mut fade = None;
+            if words.len() > 2 && words[words.len() - 1].starts_with("fade=") {
+                let seconds = &words.pop().unwrap()["fade=".len()..];
+                let seconds = seconds.parse::<f64>()
+                    .ok()
+                    .filter(|seconds| *seconds >= 0.0)
+                    .ok_or_else(|| format!("Invalid fade, expected seconds: {}", line))?;
+                fade = Some(Duration::from_millis((seconds * 1000.0) as u64));
+            }
+            if words.len() < 2 {
+                return Err(format!("Missing what to show: {}", line))
+            }
+            entries.push(Entry {
+                time: time,
+                content: words[1..].join(" ").parse()?,
+                fade: fade,
+            });
+        }
+        if entries.is_empty() {
+            return Err("The schedule has no entries".into())
+        }
+        let uses_sun = entries.iter().any(|entry| match entry.time {
+            TimeOfDay::Fixed(_) => false,
+            _ => true,
+        });
+        if uses_sun && location.is_none() {
+            return Err("Sunrise and sunset need a location line".into())
+        }
+        Ok(Schedule { location: location, entries: entries })
+    }
+}
+
+/// Central European Time, whose clocks go forward at 1:00 UTC on the last
+/// Sunday in March and back on the last Sunday in October.
+#[cfg(test)]
+#[derive(Debug, Clone, Copy)]
+struct CentralEuropeanTime;
+
+#[cfg(test)]
+impl CentralEuropeanTime {
+    fn offset_at(utc: &NaiveDateTime) -> FixedOffset {
+        use chrono::Datelike;
+
+        let change = |month: u32| {
+            let last = NaiveDate::from_ymd_opt(utc.year(), month + 1, 1).unwrap().pred_opt().unwrap();
+            let sunday = last - ChronoDuration::days(last.weekday().num_days_from_sunday() as i64);
+            sunday.and_hms_opt(1, 0, 0).unwrap()
+        };
+        let summer = *utc >= change(3) && *utc < change(10);
+        FixedOffset::east_opt(if summer { 7200 } else { 3600 }).unwrap()
+    }
+}
+
+#[cfg(test)]
+impl TimeZone for CentralEuropeanTime {
+    type Offset = FixedOffset;
+
+    fn from_offset(_: &FixedOffset) -> CentralEuropeanTime {
+        CentralEuropeanTime
+    }
+
+    fn offset_from_local_date(&self, local: &NaiveDate) -> LocalResult<FixedOffset> {
+        self.offset_from_local_datetime(&local.and_hms_opt(12, 0, 0).unwrap())
+    }
+
+    fn offset_from_local_datetime(&self, local: &NaiveDateTime) -> LocalResult<FixedOffset> {
+        // Winter and summer time, where they are in effect at that time.
+        let offsets: Vec<FixedOffset> = [3600, 7200].iter()
+            .map(|&seconds| FixedOffset::east_opt(seconds).unwrap())
+            .filter(|offset| {
+                let utc = *local - ChronoDuration::seconds(offset.local_minus_utc() as i64);
+                CentralEuropeanTime::offset_at(&utc) == *offset
+            })
+            .collect();
+        match offsets.len() {
+            0 => LocalResult::None,
+            1 => LocalResult::Single(offsets[0]),
+            _ => LocalResult::Ambiguous(offsets[0], offsets[1]),
+        }
+    }
+
+    fn offset_from_utc_date(&self, utc: &NaiveDate) -> FixedOffset {
+        CentralEuropeanTime::offset_at(&utc.and_hms_opt(0, 0, 0).unwrap())
+    }
+
+    fn offset_from_utc_datetime(&self, utc: &NaiveDateTime) -> FixedOffset {
+        CentralEuropeanTime::offset_at(utc)
+    }
+}
+
+#[cfg(test)]
+fn at<Tz: TimeZone>(zone: &Tz, year: i32, month: u32, day: u32, hour: u32,
+                    minute: u32) -> DateTime<Tz> {
+    let date = NaiveDate::from_ymd_opt(year, month, day).unwrap();
+    first_local(zone, date.and_hms_opt(hour, minute, 0).unwrap()).unwrap()
+}
+
+#[test]
+fn test_parse_time() {
+    assert_eq!(parse_time("07:00"), Ok(TimeOfDay::Fixed(7 * 60)));
+    assert_eq!(parse_time("23:59"), Ok(TimeOfDay::Fixed(23 * 60 + 59)));
+    assert_eq!(parse_time("sunrise"), Ok(TimeOfDay::Sunrise(0)));
+    assert_eq!(parse_time("sunrise+1:30"), Ok(TimeOfDay::Sunrise(90)));
+    assert_eq!(parse_time("sunset-0:45"), Ok(TimeOfDay::Sunset(-45)));
+    for invalid in &["24:00", "07:60", "7", "-1:00", "sunrise1:00", "sunset+1", "noon"] {
+        assert!(parse_time(invalid).is_err(), "{}", invalid);
+    }
+    assert!("07:00 /a.jpg fade=x".parse::<Schedule>().is_err());
+    assert!("sunset /a.jpg".parse::<Schedule>().is_err());
+}
+
+#[test]
+fn test_wrap_around() {
+    let zone = FixedOffset::east_opt(3600).unwrap();
+    let local = |year, month, day, hour, minute| at(&zone, year, month, day, hour, minute);
+    let schedule: Schedule = "07:00 color:#111111\n22:00 color:#222222 fade=30".parse().unwrap();
+    // After midnight the entry of the evening before is still shown.
+    let night = local(2017, 6, 21, 3, 0);
+    assert_eq!(schedule.current(night).content.title(), "Background Color: 222222");
+    assert_eq!(schedule.next_change(night), local(2017, 6, 21, 7, 0));
+    let evening = local(2017, 6, 21, 23, 0);
+    assert_eq!(schedule.current(evening).fade, Some(Duration::from_secs(30)));
+    assert_eq!(schedule.next_change(evening), local(2017, 6, 22, 7, 0));
+}
+
+#[test]
+fn test_daylight_saving_time() {
+    let local = |year, month, day, hour, minute| {
+        at(&CentralEuropeanTime, year, month, day, hour, minute)
+    };
+    let schedule: Schedule = "02:30 color:#111111\n07:00 color:#222222".parse().unwrap();
+    // On 26 March 2017 the clocks went from 2:00 to 3:00, the entry starts
+    // an hour after the time it was given for.
+    let spring = local(2017, 3, 26, 1, 0);
+    assert_eq!(schedule.next_change(spring), local(2017, 3, 26, 3, 30));
+    assert_eq!(schedule.current(local(2017, 3, 26, 4, 0)).content.title(),
+               "Background Color: 111111");
+    // On 29 October 2017 they went back from 3:00 to 2:00, the entry starts
+    // the first time it is 2:30.
+    let autumn = local(2017, 10, 29, 1, 0);
+    let start = schedule.next_change(autumn);
+    assert_eq!(start, local(2017, 10, 29, 2, 30));
+    assert_eq!(start.timestamp(), local(2017, 10, 29, 0, 0).timestamp() + 150 * 60);
+}
+
+#[test]
+fn test_sun_offsets() {
+    let zone = FixedOffset::east_opt(7200).unwrap();
+    let local = |year, month, day, hour, minute| at(&zone, year, month, day, hour, minute);
+    let schedule: Schedule = "location 52.52 13.40\nsunrise color:#111111\n\
+                              sunrise+1:30 color:#222222\nsunset-0:30 color:#333333"
+        .parse().unwrap();
+    let date = NaiveDate::from_ymd_opt(2017, 6, 21).unwrap();
+    let sunrise = schedule.start_on(0, date, &zone).unwrap();
+    assert_eq!(schedule.start_on(1, date, &zone), Some(sunrise + ChronoDuration::minutes(90)));
+    // Berlin, around the longest day.
+    assert!(sunrise > local(2017, 6, 21, 4, 30) && sunrise < local(2017, 6, 21, 5, 0));
+    let evening = schedule.start_on(2, date, &zone).unwrap();
+    assert!(evening > local(2017, 6, 21, 21, 0) && evening < local(2017, 6, 21, 21, 30));
+    // Without a sunrise during polar night the entries are skipped.
+    let polar: Schedule = "location 80.0 15.0\n12:00 color:#111111\nsunrise color:#222222"
+        .parse().unwrap();
+    let winter = NaiveDate::from_ymd_opt(2017, 12, 21).unwrap();
+    assert_eq!(polar.start_on(1, winter, &zone), None);
+    assert!(polar.start_on(0, winter, &zone).is_some());
+}
@@ -0,0 +1,150 @@
+//! Choosing the pixel format of the `wl_shm` buffers the backgrounds are
+//! drawn into, among the ones the compositor advertises.
+
+use std::str::FromStr;
+
+use image::RgbaImage;
+
+use wayland_client::EventQueueHandle;
+use wayland_client::protocol::wl_shm::{self, Format as WlShmFormat};
+
+use convert;
+use dither::Dither;
+use precise::PreciseImage;
+
+/// Collects the formats advertised by `wl_shm.format` events.
+pub struct ShmFormats {
+    pub formats: Vec<WlShmFormat>,
+}
+
+impl ShmFormats {
+    pub fn new() -> ShmFormats {
+        ShmFormats { formats: Vec::new() }
+    }
+}
+
+impl wl_shm::Handler for ShmFormats {
+    fn format(&mut self,
+              _evqh: &mut EventQueueHandle,
+              _proxy: &wl_shm::WlShm,
+              format: WlShmFormat) {
+        self.formats.push(format);
+    }
+}
+
+declare_handler!(ShmFormats, wl_shm::Handler, wl_shm::WlShm);
+
+/// Which buffer formats are preferred.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BufferFormat {
+    /// 8 bits per channel. Opaque backgrounds leave out the alpha channel,
+    /// so the compositor can skip blending them.
+    Auto,
+
+    /// 10 bits per channel (XRGB2101010) for opaque backgrounds, for
+    /// displays with deeper color. Backgrounds are encoded from the floats
+    /// they are rendered in, before they are rounded to 8 bits; only the
+    /// frames of transitions are blended in 8 bits and widened. Backgrounds
+    /// with transparency keep ARGB8888, ARGB2101010 has only 2 bits of
+    /// alpha.
+    Deep,
+
+    /// 16 bits per pixel (RGB565) for opaque backgrounds, at half the
+    /// memory.
+    LowMemory,
+}
+
+impl FromStr for BufferFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<BufferFormat, String> {
+        match s {
+            "auto"       => Ok(BufferFormat::Auto),
+            "deep"       => Ok(BufferFormat::Deep),
+            "low-memory" => Ok(BufferFormat::LowMemory),
+            _            => Err(format!("Invalid buffer format: {}", s)),
+        }
+    }
+}
+
+impl BufferFormat {
+    /// The format to draw an image in, `opaque` if it has no transparent
+    /// pixels (see `is_opaque`). Every compositor supports ARGB8888 and
+    /// XRGB8888, the others only if they were advertised.
+    pub fn choose(&self, opaque: bool, advertised: &[WlShmFormat]) -> WlShmFormat {
+        let supported = |format: WlShmFormat| advertised.contains(&format);
+        match *self {
+            BufferFormat::Deep if opaque && supported(WlShmFormat::Xrgb2101010) =>
+                WlShmFormat::Xrgb2101010,
+            BufferFormat::LowMemory if opaque && supported(WlShmFormat::Rgb565) =>
+                WlShmFormat::Rgb565,
+            _ if opaque => WlShmFormat::Xrgb8888,
+            _ => WlShmFormat::Argb8888,
+        }
+    }
+}
+
+/// Whether every pixel of `image` is opaque. This looks at every pixel, so
+/// it is done once per rendered background and not for every frame.
+pub fn is_opaque(image: &RgbaImage) -> bool {
+    image.pixels().all(|pixel| pixel[3] == 255)
+}
+
+/// Converts a straight RGBA image to the layout of `format`, returning the
+/// pixels and the stride. XRGB2101010 is encoded from `precise`, the image
+/// before it was rounded to 8 bits, if it is given. RGB565 is rounded with
+/// `dither`.
+pub fn encode(image: &RgbaImage,
+              precise: Option<&PreciseImage>,
+              format: WlShmFormat,
+              dither: Dither) -> (Vec<u8>, u32) {
+    let width = image.width();
+    if let (WlShmFormat::Xrgb2101010, Some(precise)) = (format, precise) {
+        return (convert::precise_to_xrgb2101010(precise), width * 4)
+    }
+    let mut pixels = image.clone().into_vec();
+    match format {
+        WlShmFormat::Xrgb8888 => {
+            convert::rgba_to_xrgb(&mut pixels);
+            (pixels, width * 4)
+        },
+        WlShmFormat::Xrgb2101010 => (convert::rgba_to_xrgb2101010(&pixels), width * 4),
+        WlShmFormat::Rgb565 => (convert::rgba_to_rgb565(&pixels, width, dither), width * 2),
+        _ => {
+            convert::rgba_to_argb_premultiplied(&mut pixels);
+            (pixels, width * 4)
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use image::{ImageBuffer, Rgba};
+
+    #[test]
+    fn test_choose() {
+        use wayland_client::protocol::wl_shm::Format::*;
+        let all = [Argb8888, Xrgb8888, Argb2101010, Xrgb2101010, Rgb565];
+        let basic = [Argb8888, Xrgb8888];
+        let cases = [
+            (BufferFormat::Auto,      true,  &all[..],   Xrgb8888),
+            (BufferFormat::Auto,      false, &all[..],   Argb8888),
+            (BufferFormat::Deep,      true,  &all[..],   Xrgb2101010),
+            (BufferFormat::Deep,      false, &all[..],   Argb8888),
+            (BufferFormat::Deep,      true,  &basic[..], Xrgb8888),
+            (BufferFormat::LowMemory, true,  &all[..],   Rgb565),
+            (BufferFormat::LowMemory, false, &all[..],   Argb8888),
+            (BufferFormat::LowMemory, true,  &basic[..], Xrgb8888),
+        ];
+        for &(format, opaque, advertised, expected) in &cases {
+            assert!(format.choose(opaque, advertised) == expected, "{:?} {}", format, opaque);
+        }
+
+        let mut image = ImageBuffer::from_pixel(2, 2, Rgba { data: [1, 2, 3, 255] });
+        assert!(is_opaque(&image));
+        image.put_pixel(1, 1, Rgba { data: [1, 2, 3, 254] });
+        assert!(!is_opaque(&image));
+    }
+}
@@ -0,0 +1,58 @@
+//! Sunrise and sunset times, computed offline with the sunrise equation.
+
+use std::f64::consts::PI;
+
+/// Julian date of the Unix epoch.
+const UNIX_EPOCH_JULIAN: f64 = 2440587.5;
+/// Julian date of the J2000 epoch the equation is expressed in.
+const J2000: f64 = 2451545.0;
+
+fn sin_deg(degrees: f64) -> f64 {
+    (degrees * PI / 180.0).sin()
+}
+
+fn cos_deg(degrees: f64) -> f64 {
+    (degrees * PI / 180.0).cos()
+}
+
+/// Sunrise and sunset on the day containing the Unix timestamp `day`, at
+/// `latitude` degrees north and `longitude` degrees east. Both are returned
+/// as Unix timestamps.
+///
+/// Returns `None` during polar day and polar night, when the sun does not
+/// rise or set at all.
+pub fn sunrise_sunset(day: i64, latitude: f64, longitude: f64) -> Option<(i64, i64)> {
+    let julian_day = (day as f64 / 86400.0 + UNIX_EPOCH_JULIAN).round();
+    // Days since J2000, adjusted to the local mean solar noon.
+    let mean_solar_noon = julian_day - J2000 + 0.0008 - longitude / 360.0;
+    let anomaly = (357.5291 + 0.98560028 * mean_solar_noon) % 360.0;
+    let center = 1.9148 * sin_deg(anomaly)
+        + 0.02 * sin_deg(2.0 * anomaly)
+        + 0.0003 * sin_deg(3.0 * anomaly);
+    let ecliptic_longitude = (anomaly + center + 180.0 + 102.9372) % 360.0;
+    let transit = J2000 + mean_solar_noon
+        + 0.0053 * sin_deg(anomaly)
+        - 0.0069 * sin_deg(2.0 * ecliptic_longitude);
+    let declination_sin = sin_deg(ecliptic_longitude) * sin_deg(23.4397);
+    let declination_cos = (1.0 - declination_sin * declination_sin).sqrt();
+    // -0.833 degrees accounts for refraction and the size of the sun's disc.
+    let hour_angle_cos = (sin_deg(-0.833) - sin_deg(latitude) * declination_sin)
+        / (cos_deg(latitude) * declination_cos);
+    if hour_angle_cos < -1.0 || hour_angle_cos > 1.0 {
+        return None
+    }
+    let hour_angle = hour_angle_cos.acos() * 180.0 / PI;
+    let to_unix = |julian: f64| ((julian - UNIX_EPOCH_JULIAN) * 86400.0).round() as i64;
+    Some((to_unix(transit - hour_angle / 360.0), to_unix(transit + hour_angle / 360.0)))
+}
+
+#[test]
+fn test_sunrise_sunset() {
+    // Berlin on 2017-06-21, sunrise 02:43 and sunset 19:33 UTC.
+    let (sunrise, sunset) = sunrise_sunset(1498046400, 52.52, 13.40).unwrap();
+    assert!((sunrise - 1498012980).abs() < 120, "sunrise at {}", sunrise);
+    assert!((sunset - 1498073580).abs() < 120, "sunset at {}", sunset);
+    // Tromsø has midnight sun and polar night.
+    assert_eq!(sunrise_sunset(1498046400, 69.65, 18.96), None);
+    assert_eq!(sunrise_sunset(1513857600, 69.65, 18.96), None);
+}
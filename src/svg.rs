@@ -0,0 +1,126 @@
+//! Vector images, rasterized at the size they are shown at instead of being
+//! scaled as a bitmap, so they stay crisp on every output.
+
+use std::sync::Arc;
+
+use image::{ImageBuffer, Rgba, RgbaImage};
+use resvg::{self, tiny_skia, usvg};
+use resvg::usvg::fontdb;
+
+thread_local! {
+    /// The fonts installed on the system, which take a while to find, so
+    /// they are only loaded for the first document with text.
+    static SYSTEM_FONTS: Arc<fontdb::Database> = {
+        let mut fonts = fontdb::Database::new();
+        fonts.load_system_fonts();
+        Arc::new(fonts)
+    };
+}
+
+/// Whether `data` looks like an SVG document rather than a bitmap.
+pub fn is_svg(data: &[u8]) -> bool {
+    // Compressed SVG, other gzipped files are not.
+    if data.starts_with(&[0x1f, 0x8b]) {
+        return usvg::decompress_svgz(data).map_or(false, |data| is_svg(&data))
+    }
+    let start = &data[..data.len().min(1024)];
+    let start = String::from_utf8_lossy(start);
+    let start = start.trim_start_matches('\u{feff}').trim_start();
+    (start.starts_with("<?xml") || start.starts_with("<!--") || start.starts_with("<svg")
+     || start.starts_with("<!DOCTYPE svg"))
+        && start.contains("<svg")
+}
+
+pub struct Svg {
+    tree: usvg::Tree,
+}
+
+impl Svg {
+    /// Parses a document, with the system fonts for its text.
+    pub fn parse(data: &[u8]) -> Result<Svg, String> {
+        let mut options = usvg::Options::default();
+        options.fontdb = SYSTEM_FONTS.with(|fonts| fonts.clone());
+        Svg::parse_with(data, &options)
+    }
+
+    /// Parses a document with other options, e.g. other fonts.
+    pub fn parse_with(data: &[u8], options: &usvg::Options) -> Result<Svg, String> {
+        usvg::Tree::from_data(data, options)
+            .map(|tree| Svg { tree: tree })
+            .map_err(|err| format!("Could not read SVG: {}", err))
+    }
+
+    /// The size the document asks to be shown at, in pixels.
+    pub fn size(&self) -> (f64, f64) {
+        let size = self.tree.size();
+        (size.width() as f64, size.height() as f64)
+    }
+
+    /// Rasterizes the whole document, scaled to `width` x `height`.
+    pub fn rasterize(&self, width: u32, height: u32) -> RgbaImage {
+        let (width, height) = (width.max(1), height.max(1));
+        let mut pixmap = tiny_skia::Pixmap::new(width, height)
+            .expect("Could not allocate SVG canvas");
+        let (svg_width, svg_height) = self.size();
+        let transform = tiny_skia::Transform::from_scale(
+            (width as f64 / svg_width) as f32,
+            (height as f64 / svg_height) as f32);
+        resvg::render(&self.tree, transform, &mut pixmap.as_mut());
+
+        // tiny-skia works with premultiplied alpha, the rest of the
+        // pipeline with straight alpha.
+        let pixels = pixmap.pixels();
+        ImageBuffer::from_fn(width, height, |x, y| {
+            let color = pixels[(y * width + x) as usize].demultiply();
+            Rgba { data: [color.red(), color.green(), color.blue(), color.alpha()] }
+        })
+    }
+}
+
+#[test]
+fn test_is_svg() {
+    let document = br#"<?xml version="1.0"?><svg xmlns="http://www.w3.org/2000/svg"/>"#;
+    assert!(is_svg(document));
+    assert!(is_svg(b"\xef\xbb\xbf  <svg width=\"10\" height=\"10\"/>"));
+    assert!(!is_svg(b"<?xml version=\"1.0\"?><html/>"));
+    assert!(!is_svg(b"\x89PNG\r\n\x1a\n"));
+
+    // A gzip stream with the data in an uncompressed block.
+    let gzip = |data: &[u8]| {
+        let mut crc = !0u32;
+        for &byte in data {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            }
+        }
+        let length = data.len() as u16;
+        let mut stream = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff, 1];
+        stream.extend_from_slice(&[length as u8, (length >> 8) as u8,
+                                   !length as u8, (!length >> 8) as u8]);
+        stream.extend_from_slice(data);
+        for value in &[!crc, data.len() as u32] {
+            stream.extend_from_slice(&[*value as u8, (*value >> 8) as u8,
+                                       (*value >> 16) as u8, (*value >> 24) as u8]);
+        }
+        stream
+    };
+    assert!(is_svg(&gzip(document)));
+    assert!(!is_svg(&gzip(b"P6 1 1 255 abc")));
+    assert!(!is_svg(&[0x1f, 0x8b, 0x08, 0x00]));
+}
+
+#[test]
+fn test_rasterize() {
+    let document = br##"<svg xmlns="http://www.w3.org/2000/svg" width="20" height="10">
+                        <rect width="10" height="10" fill="#ff0000"/></svg>"##;
+    let svg = Svg::parse(document).unwrap();
+    assert_eq!(svg.size(), (20.0, 10.0));
+    // Rasterized at twice its size, the left half is red and the right one
+    // transparent.
+    let image = svg.rasterize(40, 20);
+    assert_eq!(image.dimensions(), (40, 20));
+    assert_eq!(image.get_pixel(5, 10).data, [255, 0, 0, 255]);
+    assert_eq!(image.get_pixel(35, 10)[3], 0);
+    assert!(Svg::parse(b"<svg").is_err());
+}
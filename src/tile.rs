@@ -0,0 +1,185 @@
+//! The tile mode: repeating the image over the whole output.
+
+use std::str::FromStr;
+
+use image::{FilterType, ImageBuffer};
+
+use precise::PreciseImage;
+use resample::{self, Resampling};
+
+/// How consecutive tiles are oriented.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TileRepeat {
+    /// Every tile is the same.
+    Repeat,
+
+    /// Every other column is mirrored horizontally.
+    MirrorX,
+
+    /// Every other row is mirrored vertically.
+    MirrorY,
+
+    /// Both, so that neighbouring tiles always meet at matching edges.
+    Mirror,
+}
+
+impl FromStr for TileRepeat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TileRepeat, String> {
+        match s {
+            "repeat"   => Ok(TileRepeat::Repeat),
+            "mirror-x" => Ok(TileRepeat::MirrorX),
+            "mirror-y" => Ok(TileRepeat::MirrorY),
+            "mirror"   => Ok(TileRepeat::Mirror),
+            _          => Err(format!("Invalid tile repeat: {}", s)),
+        }
+    }
+}
+
+/// Where the grid of tiles starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TileAnchor {
+    /// A tile starts at the top left corner of the output.
+    TopLeft,
+
+    /// A tile is centered on the output.
+    Center,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TileOptions {
+    /// How much to scale the image before tiling it.
+    pub scale: f64,
+    pub anchor: TileAnchor,
+    /// How far the grid is moved from the anchor, in pixels.
+    pub offset: (i64, i64),
+    pub repeat: TileRepeat,
+}
+
+impl Default for TileOptions {
+    fn default() -> TileOptions {
+        TileOptions {
+            scale: 1.0,
+            anchor: TileAnchor::TopLeft,
+            offset: (0, 0),
+            repeat: TileRepeat::Repeat,
+        }
+    }
+}
+
+impl TileOptions {
+    /// Parses a `--tile-offset` value, either `X,Y` in pixels from the top
+    /// left corner or `center[:X,Y]`.
+    pub fn set_offset(&mut self, s: &str) -> Result<(), String> {
+        let (anchor, offset) = if s == "center" {
+            (TileAnchor::Center, "0,0")
+        } else if s.starts_with("center:") {
+            (TileAnchor::Center, &s["center:".len()..])
+        } else {
+            (TileAnchor::TopLeft, s)
+        };
+        let offset = offset.split(',')
+            .map(|c| c.trim().parse::<i64>().ok())
+            .collect::<Option<Vec<_>>>();
+        match offset {
+            Some(ref offset) if offset.len() == 2 => {
+                self.anchor = anchor;
+                self.offset = (offset[0], offset[1]);
+                Ok(())
+            },
+            _ => Err(format!("Invalid tile offset, expected X,Y or center[:X,Y]: {}", s)),
+        }
+    }
+}
+
+/// Maps an output coordinate to the coordinate in the tile it falls on,
+/// given where the first tile starts.
+fn tile_coordinate(position: i64, origin: i64, size: i64, mirror: bool) -> u32 {
+    let relative = position - origin;
+    // Rounds towards negative infinity, so tiles left of the origin work too.
+    let index = if relative >= 0 {
+        relative / size
+    } else {
+        (relative - size + 1) / size
+    };
+    let local = relative - index * size;
+    if mirror && index % 2 != 0 {
+        (size - 1 - local) as u32
+    } else {
+        local as u32
+    }
+}
+
+/// Repeats the image over an output of `scr_width` x `scr_height`, writing
+/// each pixel straight into the output instead of tiling a larger canvas
+/// and cropping it.
+pub fn tile(image: &PreciseImage, scr_width: u32, scr_height: u32,
+            options: &TileOptions, resampling: Resampling) -> PreciseImage {
+    let (img_width, img_height) = image.dimensions();
+    let tile_width = ((img_width as f64 * options.scale).round() as u32).max(1);
+    let tile_height = ((img_height as f64 * options.scale).round() as u32).max(1);
+    let tile = if (tile_width, tile_height) == (img_width, img_height) {
+        image.clone()
+    } else {
+        resample::resize_precise(image, tile_width, tile_height, FilterType::Gaussian,
+                                 resampling)
+    };
+
+    let (origin_x, origin_y) = match options.anchor {
+        TileAnchor::TopLeft => options.offset,
+        TileAnchor::Center => ((scr_width as i64 - tile_width as i64) / 2 + options.offset.0,
+                               (scr_height as i64 - tile_height as i64) / 2 + options.offset.1),
+    };
+    let (mirror_x, mirror_y) = match options.repeat {
+        TileRepeat::Repeat  => (false, false),
+        TileRepeat::MirrorX => (true, false),
+        TileRepeat::MirrorY => (false, true),
+        TileRepeat::Mirror  => (true, true),
+    };
+
+    ImageBuffer::from_fn(scr_width, scr_height, |x, y| {
+        let tile_x = tile_coordinate(x as i64, origin_x, tile_width as i64, mirror_x);
+        let tile_y = tile_coordinate(y as i64, origin_y, tile_height as i64, mirror_y);
+        *tile.get_pixel(tile_x, tile_y)
+    })
+}
+
+#[test]
+fn test_tile_coordinate() {
+    // Tiles of 4 starting at 2, so positions 2 to 5 are the first tile.
+    let coordinates = |mirror| (-6..10).map(|x| tile_coordinate(x, 2, 4, mirror)).collect::<Vec<_>>();
+    assert_eq!(coordinates(false), vec![0, 1, 2, 3, 0, 1, 2, 3, 0, 1, 2, 3, 0, 1, 2, 3]);
+    // Every other tile is mirrored, the first one is not, neither are those
+    // an even number of tiles left of it.
+    assert_eq!(coordinates(true), vec![0, 1, 2, 3, 3, 2, 1, 0, 0, 1, 2, 3, 3, 2, 1, 0]);
+    // Origins left of the output work the same.
+    assert_eq!(tile_coordinate(0, -5, 4, false), 1);
+    assert_eq!(tile_coordinate(0, -5, 4, true), 2);
+}
+
+#[test]
+fn test_tile() {
+    use image::{Rgba, RgbaImage};
+
+    use dither::Dither;
+    use precise;
+
+    let mut options = TileOptions::default();
+    options.set_offset("center:-1,2").unwrap();
+    assert_eq!((options.anchor, options.offset), (TileAnchor::Center, (-1, 2)));
+    assert!(options.set_offset("1").is_err());
+    assert!(options.set_offset("center:a,b").is_err());
+    options.repeat = TileRepeat::Mirror;
+
+    // Each pixel's red channel is its column, the green one its row.
+    let image = precise::from_rgba(&RgbaImage::from_fn(3, 2, |x, y| {
+        Rgba { data: [x as u8, y as u8, 0, 255] }
+    }));
+    let tiled = precise::quantize(&tile(&image, 8, 8, &options, Resampling::default()), Dither::None);
+    // Centered a tile starts at 2, 3, moved by the offset it starts at 1, 5.
+    let columns: Vec<u8> = (0..8).map(|x| tiled.get_pixel(x, 5)[0]).collect();
+    assert_eq!(columns, vec![0, 0, 1, 2, 2, 1, 0, 0]);
+    let rows: Vec<u8> = (0..8).map(|y| tiled.get_pixel(1, y)[1]).collect();
+    assert_eq!(rows, vec![0, 0, 1, 1, 0, 0, 1, 1]);
+}
@@ -0,0 +1,220 @@
+//! Rotation and flipping of the image, from `--rotate` and `--flip`.
+//!
+//! Both options take an optional output selector, so that for example
+//! portrait monitors can show a rotated version of a landscape photo:
+//!
+//! ```text
+//! --rotate 180 --rotate portrait=90 --flip 1=h
+//! ```
+//!
+//! Rules are applied in the order they are given, so a later rule matching
+//! an output overrides what an earlier one set.
+
+use image::imageops::{flip_horizontal, flip_vertical, rotate180, rotate270, rotate90};
+
+use way_cooler_client_helpers::wayland::Resolution;
+
+use precise::PreciseImage;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Transform {
+    /// Clockwise rotation, in multiples of 90 degrees.
+    quarter_turns: u8,
+    flip_horizontal: bool,
+    flip_vertical: bool,
+}
+
+impl Transform {
+    /// Whether the rotation swaps the width and height of the image.
+    pub fn swaps_dimensions(&self) -> bool {
+        self.quarter_turns % 2 == 1
+    }
+
+    /// Rotates and then flips the image.
+    pub fn apply(&self, image: PreciseImage) -> PreciseImage {
+        let image = match self.quarter_turns {
+            1 => rotate90(&image),
+            2 => rotate180(&image),
+            3 => rotate270(&image),
+            _ => image,
+        };
+        let image = if self.flip_horizontal { flip_horizontal(&image) } else { image };
+        if self.flip_vertical { flip_vertical(&image) } else { image }
+    }
+}
+
+/// Which outputs a rule applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Selector {
+    All,
+    /// The n-th output, counted from 0 in the order the compositor
+    /// announced them.
+    Index(usize),
+    /// Outputs that are taller than they are wide.
+    Portrait,
+    /// Outputs that are at least as wide as they are tall.
+    Landscape,
+}
+
+impl Selector {
+    pub fn matches(&self, index: usize, resolution: Resolution) -> bool {
+        match *self {
+            Selector::All => true,
+            Selector::Index(i) => i == index,
+            Selector::Portrait => resolution.h > resolution.w,
+            Selector::Landscape => resolution.h <= resolution.w,
+        }
+    }
+}
+
+/// Splits the `SELECTOR=` prefix off an option value.
+pub fn split_selector(s: &str) -> Result<(Selector, &str), String> {
+    let mut parts = s.splitn(2, '=');
+    let first = parts.next().unwrap_or("");
+    let value = match parts.next() {
+        Some(value) => value,
+        None => return Ok((Selector::All, first)),
+    };
+    let selector = match first {
+        "all" => Selector::All,
+        "portrait" => Selector::Portrait,
+        "landscape" => Selector::Landscape,
+        index => Selector::Index(index.parse()
+                                 .map_err(|_| format!("Invalid output selector: {}", index))?),
+    };
+    Ok((selector, value))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Rule {
+    Rotate(u8),
+    Flip { horizontal: bool, vertical: bool },
+}
+
+/// The `--rotate` and `--flip` rules, resolved per output.
+#[derive(Debug, Clone, Default)]
+pub struct OutputTransforms {
+    rules: Vec<(Selector, Rule)>,
+}
+
+impl OutputTransforms {
+    /// Adds a `--rotate` value, e.g. `90` or `portrait=270`.
+    pub fn add_rotation(&mut self, s: &str) -> Result<(), String> {
+        let (selector, degrees) = split_selector(s)?;
+        let quarter_turns = match degrees {
+            "0" => 0,
+            "90" => 1,
+            "180" => 2,
+            "270" => 3,
+            _ => return Err(format!("Invalid rotation, expected 0, 90, 180 or 270: {}", degrees)),
+        };
+        self.rules.push((selector, Rule::Rotate(quarter_turns)));
+        Ok(())
+    }
+
+    /// Adds a `--flip` value, e.g. `h`, `v`, `hv` or `1=none`.
+    pub fn add_flip(&mut self, s: &str) -> Result<(), String> {
+        let (selector, flip) = split_selector(s)?;
+        let (horizontal, vertical) = match flip {
+            "none" => (false, false),
+            "h" => (true, false),
+            "v" => (false, true),
+            "hv" | "vh" => (true, true),
+            _ => return Err(format!("Invalid flip, expected h, v, hv or none: {}", flip)),
+        };
+        self.rules.push((selector, Rule::Flip { horizontal: horizontal, vertical: vertical }));
+        Ok(())
+    }
+
+    /// The transform to apply to the image shown on the `index`-th output.
+    pub fn for_output(&self, index: usize, resolution: Resolution) -> Transform {
+        let mut transform = Transform::default();
+        for &(selector, rule) in &self.rules {
+            if !selector.matches(index, resolution) {
+                continue
+            }
+            match rule {
+                Rule::Rotate(quarter_turns) => transform.quarter_turns = quarter_turns,
+                Rule::Flip { horizontal, vertical } => {
+                    transform.flip_horizontal = horizontal;
+                    transform.flip_vertical = vertical;
+                }
+            }
+        }
+        transform
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use image::{ImageBuffer, Rgba};
+
+    fn resolution(w: u32, h: u32) -> Resolution {
+        Resolution { w: w, h: h }
+    }
+
+    /// The red channel of every pixel, row by row.
+    fn pixels(image: &PreciseImage) -> (u32, u32, Vec<u8>) {
+        let (width, height) = image.dimensions();
+        (width, height, image.pixels().map(|pixel| pixel[0] as u8).collect())
+    }
+
+    #[test]
+    fn test_apply() {
+        // 1 2 3
+        // 4 5 6
+        let image: PreciseImage = ImageBuffer::from_fn(3, 2, |x, y| {
+            Rgba { data: [(1 + x + y * 3) as f32, 0.0, 0.0, 255.0] }
+        });
+        let transform = |s: &str| {
+            let mut transforms = OutputTransforms::default();
+            for rule in s.split(' ') {
+                if rule.starts_with("r") {
+                    transforms.add_rotation(&rule[1..]).unwrap();
+                } else {
+                    transforms.add_flip(rule).unwrap();
+                }
+            }
+            let transform = transforms.for_output(0, resolution(1920, 1080));
+            (transform.swaps_dimensions(), pixels(&transform.apply(image.clone())))
+        };
+        assert_eq!(transform("r0"), (false, (3, 2, vec![1, 2, 3, 4, 5, 6])));
+        assert_eq!(transform("r90"), (true, (2, 3, vec![4, 1, 5, 2, 6, 3])));
+        assert_eq!(transform("r180"), (false, (3, 2, vec![6, 5, 4, 3, 2, 1])));
+        assert_eq!(transform("r270"), (true, (2, 3, vec![3, 6, 2, 5, 1, 4])));
+        assert_eq!(transform("h"), (false, (3, 2, vec![3, 2, 1, 6, 5, 4])));
+        assert_eq!(transform("v"), (false, (3, 2, vec![4, 5, 6, 1, 2, 3])));
+        assert_eq!(transform("hv"), transform("r180"));
+        // Flipping happens after rotating.
+        assert_eq!(transform("r90 h"), (true, (2, 3, vec![1, 4, 2, 5, 3, 6])));
+    }
+
+    #[test]
+    fn test_selectors() {
+        let mut transforms = OutputTransforms::default();
+        transforms.add_rotation("180").unwrap();
+        transforms.add_rotation("portrait=90").unwrap();
+        transforms.add_flip("1=h").unwrap();
+        transforms.add_flip("landscape=v").unwrap();
+        let (landscape, portrait) = (resolution(1920, 1080), resolution(1080, 1920));
+
+        let first = transforms.for_output(0, landscape);
+        assert_eq!((first.quarter_turns, first.flip_horizontal, first.flip_vertical), (2, false, true));
+        // The later landscape rule overrides the flip of the second output.
+        let second = transforms.for_output(1, landscape);
+        assert_eq!((second.quarter_turns, second.flip_horizontal, second.flip_vertical), (2, false, true));
+        let second = transforms.for_output(1, portrait);
+        assert_eq!((second.quarter_turns, second.flip_horizontal, second.flip_vertical), (1, true, false));
+        let third = transforms.for_output(2, portrait);
+        assert_eq!((third.quarter_turns, third.flip_horizontal, third.flip_vertical), (1, false, false));
+
+        assert_eq!(split_selector("2=90"), Ok((Selector::Index(2), "90")));
+        assert_eq!(split_selector("all=h"), Ok((Selector::All, "h")));
+        assert_eq!(split_selector("90"), Ok((Selector::All, "90")));
+        assert!(split_selector("left=90").is_err());
+        assert!(transforms.add_rotation("45").is_err());
+        assert!(transforms.add_flip("x").is_err());
+    }
+}
@@ -0,0 +1,252 @@
+//! Transitions between the old and the new image when a background changes.
+//!
+//! A transition only computes frames, `t` of the way from one image to the
+//! other. When frames are shown is paced by the compositor's frame
+//! callbacks, see `frame::FrameHandler`.
+//!
+//! Backgrounds change, and transition, when a `--schedule` moves on to the
+//! next entry and when `SIGUSR1` reloads the options. There is no slideshow
+//! or command interface that could change them otherwise.
+
+use std::str::FromStr;
+use std::time::Duration;
+
+use image::{ImageBuffer, Rgba, RgbaImage};
+
+/// How the progress of a transition speeds up and slows down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Easing {
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+}
+
+impl Easing {
+    /// Maps the elapsed fraction of the duration to the transition progress.
+    pub fn apply(self, t: f64) -> f64 {
+        let t = t.max(0.0).min(1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t).powi(3),
+            Easing::EaseInOut => if t < 0.5 {
+                4.0 * t * t * t
+            } else {
+                1.0 - (2.0 - 2.0 * t).powi(3) / 2.0
+            },
+        }
+    }
+}
+
+impl FromStr for Easing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Easing, String> {
+        match s {
+            "linear"      => Ok(Easing::Linear),
+            "ease-in"     => Ok(Easing::EaseIn),
+            "ease-out"    => Ok(Easing::EaseOut),
+            "ease-in-out" => Ok(Easing::EaseInOut),
+            _             => Err(format!("Invalid easing: {}", s)),
+        }
+    }
+}
+
+/// The direction a wipe's edge or a sliding image moves in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransitionKind {
+    /// Blend the old image into the new one.
+    Fade,
+
+    /// Reveal the new image behind an edge moving across the output.
+    Wipe(Direction),
+
+    /// Slide the new image in, pushing the old one out.
+    Slide(Direction),
+}
+
+impl FromStr for TransitionKind {
+    type Err = String;
+
+    /// Parses `fade`, `wipe-DIRECTION` or `slide-DIRECTION`, where the
+    /// direction is `left`, `right`, `up` or `down`.
+    fn from_str(s: &str) -> Result<TransitionKind, String> {
+        if s == "fade" {
+            return Ok(TransitionKind::Fade)
+        }
+        let invalid = || format!("Invalid transition: {}", s);
+        let mut parts = s.splitn(2, '-');
+        let kind = parts.next().ok_or_else(invalid)?;
+        let direction = match parts.next() {
+            Some("left")  => Direction::Left,
+            Some("right") => Direction::Right,
+            Some("up")    => Direction::Up,
+            Some("down")  => Direction::Down,
+            _             => return Err(invalid()),
+        };
+        match kind {
+            "wipe"  => Ok(TransitionKind::Wipe(direction)),
+            "slide" => Ok(TransitionKind::Slide(direction)),
+            _       => Err(invalid()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Transition {
+    pub kind: TransitionKind,
+    pub easing: Easing,
+    /// How long the transition takes, zero swaps the images at once.
+    pub duration: Duration,
+}
+
+impl Default for Transition {
+    fn default() -> Transition {
+        Transition {
+            kind: TransitionKind::Fade,
+            easing: Easing::EaseInOut,
+            duration: Duration::from_secs(0),
+        }
+    }
+}
+
+impl Transition {
+    /// The fraction of the duration that has passed after `elapsed`,
+    /// 1 once the transition is over.
+    pub fn fraction(&self, elapsed: Duration) -> f64 {
+        let duration = duration_secs(self.duration);
+        if duration <= 0.0 {
+            1.0
+        } else {
+            (duration_secs(elapsed) / duration).min(1.0)
+        }
+    }
+
+    /// The frame `t` of the duration from `from` to `to`, before easing.
+    /// Images of different sizes (e.g. after an output changed its mode)
+    /// can't be combined and give `to` right away.
+    pub fn frame(&self, from: &RgbaImage, to: &RgbaImage, t: f64) -> RgbaImage {
+        if from.dimensions() != to.dimensions() || t >= 1.0 {
+            return to.clone()
+        }
+        let progress = self.easing.apply(t);
+        match self.kind {
+            TransitionKind::Fade => blend(from, to, progress as f32),
+            TransitionKind::Wipe(direction) => wipe(from, to, direction, progress),
+            TransitionKind::Slide(direction) => slide(from, to, direction, progress),
+        }
+    }
+}
+
+fn duration_secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1e9
+}
+
+/// Blends two images of the same size, `t` of the way from `from` to `to`.
+fn blend(from: &RgbaImage, to: &RgbaImage, t: f32) -> RgbaImage {
+    assert_eq!(from.dimensions(), to.dimensions());
+    let t = t.max(0.0).min(1.0);
+    let (width, height) = from.dimensions();
+    ImageBuffer::from_fn(width, height, |x, y| {
+        let (from, to) = (from.get_pixel(x, y), to.get_pixel(x, y));
+        let mut data = [0u8; 4];
+        for c in 0..4 {
+            data[c] = (from[c] as f32 + (to[c] as f32 - from[c] as f32) * t).round() as u8;
+        }
+        Rgba { data: data }
+    })
+}
+
+/// How many pixels of `size` the transition has covered at `progress`.
+fn covered(size: u32, progress: f64) -> u32 {
+    ((size as f64 * progress).round() as u32).min(size)
+}
+
+fn wipe(from: &RgbaImage, to: &RgbaImage, direction: Direction, progress: f64) -> RgbaImage {
+    let (width, height) = from.dimensions();
+    let (edge_x, edge_y) = (covered(width, progress), covered(height, progress));
+    ImageBuffer::from_fn(width, height, |x, y| {
+        let revealed = match direction {
+            Direction::Right => x < edge_x,
+            Direction::Left  => x >= width - edge_x,
+            Direction::Down  => y < edge_y,
+            Direction::Up    => y >= height - edge_y,
+        };
+        if revealed { *to.get_pixel(x, y) } else { *from.get_pixel(x, y) }
+    })
+}
+
+fn slide(from: &RgbaImage, to: &RgbaImage, direction: Direction, progress: f64) -> RgbaImage {
+    let (width, height) = from.dimensions();
+    let (shift_x, shift_y) = (covered(width, progress), covered(height, progress));
+    ImageBuffer::from_fn(width, height, |x, y| {
+        match direction {
+            // The new image enters at the right edge and moves left.
+            Direction::Left => if x < width - shift_x {
+                *from.get_pixel(x + shift_x, y)
+            } else {
+                *to.get_pixel(x - (width - shift_x), y)
+            },
+            Direction::Right => if x >= shift_x {
+                *from.get_pixel(x - shift_x, y)
+            } else {
+                *to.get_pixel(x + (width - shift_x), y)
+            },
+            Direction::Up => if y < height - shift_y {
+                *from.get_pixel(x, y + shift_y)
+            } else {
+                *to.get_pixel(x, y - (height - shift_y))
+            },
+            Direction::Down => if y >= shift_y {
+                *from.get_pixel(x, y - shift_y)
+            } else {
+                *to.get_pixel(x, y + (height - shift_y))
+            },
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn solid(value: u8) -> RgbaImage {
+        ImageBuffer::from_pixel(4, 2, Rgba { data: [value, value, value, 255] })
+    }
+
+    #[test]
+    fn test_transition_ends() {
+        let (from, to) = (solid(0), solid(200));
+        for kind in &["fade", "wipe-left", "wipe-down", "slide-right", "slide-up"] {
+            let transition = Transition {
+                kind: kind.parse().unwrap(),
+                easing: Easing::Linear,
+                duration: Duration::from_secs(1),
+            };
+            assert_eq!(transition.frame(&from, &to, 0.0).into_vec(), from.clone().into_vec(), "{}", kind);
+            assert_eq!(transition.frame(&from, &to, 1.0).into_vec(), to.clone().into_vec(), "{}", kind);
+        }
+    }
+
+    #[test]
+    fn test_slide_halfway() {
+        let (from, to) = (solid(0), solid(200));
+        let transition = Transition {
+            kind: TransitionKind::Slide(Direction::Left),
+            easing: Easing::Linear,
+            duration: Duration::from_secs(1),
+        };
+        let frame = transition.frame(&from, &to, 0.5);
+        let row: Vec<u8> = (0..4).map(|x| frame.get_pixel(x, 0)[0]).collect();
+        assert_eq!(row, vec![0, 0, 200, 200]);
+    }
+}
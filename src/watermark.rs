@@ -0,0 +1,212 @@
+//! A second image, e.g. a company logo, composited over the finished
+//! background of each output.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use image::FilterType;
+
+use way_cooler_client_helpers::wayland::Resolution;
+
+use backdrop;
+use hdr::ToneMapping;
+use load;
+use overlay::Anchor;
+use precise::PreciseImage;
+use resample::{self, Resampling};
+use transform::{self, Selector};
+
+/// How far the watermark is kept from the edges, relative to the output's
+/// shorter side.
+const MARGIN: f64 = 0.02;
+
+/// An image used as a watermark, with the sizes it was scaled to.
+#[derive(Debug)]
+struct Logo {
+    image: PreciseImage,
+    /// Scaled images with the opacity applied, by width, height, opacity
+    /// and whether they were scaled in linear light, so they are only
+    /// resampled once.
+    scaled: RefCell<HashMap<(u32, u32, u32, bool), Rc<PreciseImage>>>,
+}
+
+impl Logo {
+    fn new(image: PreciseImage) -> Logo {
+        Logo { image: image, scaled: RefCell::new(HashMap::new()) }
+    }
+
+    /// The image at `width` x `height`, with its alpha times `opacity`.
+    fn scaled(&self, width: u32, height: u32, opacity: f32,
+              resampling: Resampling) -> Rc<PreciseImage> {
+        let key = (width, height, opacity.to_bits(), resampling.linear);
+        self.scaled.borrow_mut().entry(key).or_insert_with(|| {
+            let mut logo = scale(&self.image, width, height, resampling);
+            for pixel in logo.pixels_mut() {
+                pixel[3] *= opacity;
+            }
+            Rc::new(logo)
+        }).clone()
+    }
+}
+
+/// The watermark of one output.
+#[derive(Debug, Clone)]
+pub struct Watermark {
+    logo: Rc<Logo>,
+    pub anchor: Anchor,
+    /// Scale of the image relative to its own size.
+    pub scale: f64,
+    /// Between 0 and 1.
+    pub opacity: f32,
+}
+
+impl Watermark {
+    /// Composites the watermark over a background, scaling it with
+    /// `resampling`.
+    pub fn draw(&self, mut background: PreciseImage, resampling: Resampling) -> PreciseImage {
+        let (scr_width, scr_height) = background.dimensions();
+        let image = &self.logo.image;
+        let width = (image.width() as f64 * self.scale).round().max(1.0) as u32;
+        let height = (image.height() as f64 * self.scale).round().max(1.0) as u32;
+        let logo = self.logo.scaled(width, height, self.opacity, resampling);
+        let margin = MARGIN * scr_width.min(scr_height) as f64;
+        let (x, y) = self.anchor.place(width as f64, height as f64,
+                                       scr_width as f64, scr_height as f64, margin);
+        backdrop::composite_at(&mut background, &logo, x.max(0.0).round() as u32,
+                               y.max(0.0).round() as u32);
+        background
+    }
+}
+
+/// Resizes a logo premultiplied, so the transparent pixels around it, whose
+/// color is arbitrary, don't bleed into its edges.
+fn scale(image: &PreciseImage, width: u32, height: u32, resampling: Resampling) -> PreciseImage {
+    if image.dimensions() == (width, height) {
+        return image.clone()
+    }
+    resample::resize_premultiplied(image, width, height, FilterType::Lanczos3, resampling)
+}
+
+#[derive(Debug, Clone)]
+enum Rule {
+    Image(Option<Rc<Logo>>),
+    Position(Anchor),
+    Scale(f64),
+    Opacity(f32),
+}
+
+/// The `--watermark` rules, resolved per output.
+#[derive(Debug, Clone, Default)]
+pub struct OutputWatermarks {
+    rules: Vec<(Selector, Rule)>,
+}
+
+impl OutputWatermarks {
+    /// Adds a `--watermark` value, e.g. `logo.png`, `1=logo-dark.png` or
+    /// `portrait=none`.
+    pub fn add_image(&mut self, s: &str, tone_mapping: &ToneMapping) -> Result<(), String> {
+        let (selector, path) = transform::split_selector(s)?;
+        let image = if path == "none" {
+            None
+        } else {
+            let data = load::read(path).map_err(|err| format!("Could not read {}: {}", path, err))?;
+            let image = load::decode(&data, path, tone_mapping)
+                .map_err(|err| format!("Could not decode {}: {}", path, err))?;
+            Some(Rc::new(Logo::new(image)))
+        };
+        self.rules.push((selector, Rule::Image(image)));
+        Ok(())
+    }
+
+    /// Adds a `--watermark-position` value, e.g. `top-left`.
+    pub fn add_position(&mut self, s: &str) -> Result<(), String> {
+        let (selector, position) = transform::split_selector(s)?;
+        self.rules.push((selector, Rule::Position(position.parse()?)));
+        Ok(())
+    }
+
+    /// Adds a `--watermark-scale` value, e.g. `0.5` or `portrait=0.3`.
+    pub fn add_scale(&mut self, s: &str) -> Result<(), String> {
+        let (selector, scale) = transform::split_selector(s)?;
+        let scale = scale.parse::<f64>().ok().filter(|scale| *scale > 0.0)
+            .ok_or_else(|| format!("Invalid watermark scale: {}", scale))?;
+        self.rules.push((selector, Rule::Scale(scale)));
+        Ok(())
+    }
+
+    /// Adds a `--watermark-opacity` value, e.g. `0.6`.
+    pub fn add_opacity(&mut self, s: &str) -> Result<(), String> {
+        let (selector, opacity) = transform::split_selector(s)?;
+        let opacity = opacity.parse::<f32>().ok().filter(|opacity| *opacity >= 0.0 && *opacity <= 1.0)
+            .ok_or_else(|| format!("Watermark opacity must be between 0 and 1: {}", opacity))?;
+        self.rules.push((selector, Rule::Opacity(opacity)));
+        Ok(())
+    }
+
+    /// The watermark of the `index`-th output, if it has one. Later rules
+    /// override earlier ones.
+    pub fn for_output(&self, index: usize, resolution: Resolution) -> Option<Watermark> {
+        let mut image = None;
+        let mut anchor = "bottom-right".parse().unwrap();
+        let (mut scale, mut opacity) = (1.0, 1.0);
+        for &(selector, ref rule) in &self.rules {
+            if !selector.matches(index, resolution) {
+                continue
+            }
+            match *rule {
+                Rule::Image(ref rule_image) => image = rule_image.clone(),
+                Rule::Position(rule_anchor) => anchor = rule_anchor,
+                Rule::Scale(rule_scale) => scale = rule_scale,
+                Rule::Opacity(rule_opacity) => opacity = rule_opacity,
+            }
+        }
+        image.map(|logo| Watermark {
+            logo: logo,
+            anchor: anchor,
+            scale: scale,
+            opacity: opacity,
+        })
+    }
+}
+
+#[test]
+fn test_watermark() {
+    use image::{ImageBuffer, Rgba, RgbaImage};
+
+    use dither::Dither;
+    use precise;
+
+    // A red square with a transparent, green border.
+    let logo = RgbaImage::from_fn(16, 16, |x, y| if x < 2 || y < 2 || x > 13 || y > 13 {
+        Rgba { data: [0, 255, 0, 0] }
+    } else {
+        Rgba { data: [255, 0, 0, 255] }
+    });
+    let watermark = Watermark {
+        logo: Rc::new(Logo::new(precise::from_rgba(&logo))),
+        anchor: "top-left".parse().unwrap(),
+        scale: 2.0,
+        opacity: 0.5,
+    };
+    let background = ImageBuffer::from_pixel(100, 100, Rgba { data: [0.0, 0.0, 0.0, 255.0] });
+    let image = watermark.draw(background, Resampling::default());
+    let image = precise::quantize(&image, Dither::None);
+    // Two pixels margin, then the center of the logo.
+    assert_eq!(image.get_pixel(18, 18).data, [128, 0, 0, 255]);
+    assert_eq!(image.get_pixel(1, 1).data, [0, 0, 0, 255]);
+    // The edges of the square show no green from the border.
+    assert!(image.pixels().all(|pixel| pixel[1] == 0));
+    // Drawing again reuses the scaled logo.
+    watermark.draw(ImageBuffer::new(100, 100), Resampling::default());
+    assert_eq!(watermark.logo.scaled.borrow().len(), 1);
+    // In linear light the edges are still free of green.
+    let image = watermark.draw(ImageBuffer::new(100, 100), Resampling { linear: true });
+    assert!(image.pixels().all(|pixel| pixel[1].abs() < 0.5));
+
+    // Nearly transparent pixels keep their color, which premultiplying
+    // them in 8 bits would round away.
+    let faint = Logo::new(ImageBuffer::from_pixel(8, 8, Rgba { data: [200.0, 100.0, 50.0, 3.0] }));
+    let pixel = *faint.scaled(16, 16, 1.0, Resampling::default()).get_pixel(8, 8);
+    assert!((pixel[0] - 200.0).abs() < 0.5 && (pixel[2] - 50.0).abs() < 0.5, "{:?}", pixel);
+}